@@ -0,0 +1,238 @@
+use std::ptr;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::cell::{Cell, RefCell};
+
+use gio;
+use gio::prelude::*;
+use gtk;
+use gtk::prelude::*;
+use gio_sys;
+use glib_sys;
+use gobject_sys;
+
+use crate::config;
+
+const INTERFACE_SCHEMA: &str = "org.gnome.desktop.interface";
+
+const PORTAL_NAMESPACE: &str = "org.freedesktop.appearance";
+const PORTAL_KEY: &str = "color-scheme";
+
+thread_local!{
+    /// Whether the desktop prefers dark applications, None if it doesn't say
+    static DARK: Cell<Option<bool>> = Cell::new(None);
+    /// The prefer-dark setting from before the desktop's preference was applied to it
+    static USER_PREFER_DARK: Cell<Option<bool>> = Cell::new(None);
+    /// The desktop's monospace font as a Pango font description
+    static MONOSPACE: RefCell<Option<String>> = RefCell::new(None);
+    /// Kept around for their change notifications
+    static WATCHED: RefCell<Vec<gio::Settings>> = RefCell::new(Vec::new());
+    /// The settings portal, kept for its SettingChanged signal
+    static PORTAL: Cell<*mut gio_sys::GDBusProxy> = Cell::new(ptr::null_mut());
+}
+
+/// The GNOME interface settings, if they're installed and have the key
+pub fn desktop_interface(key: &str) -> Option<gio::Settings> {
+    let schema = gio::SettingsSchemaSource::get_default()?.lookup(INTERFACE_SCHEMA, true)?;
+    if schema.has_key(key) {
+        Some(gio::Settings::new(INTERFACE_SCHEMA))
+    } else {
+        None
+    }
+}
+
+pub fn is_dark() -> Option<bool> {
+    DARK.with(|d| d.get())
+}
+
+fn set_dark(dark: Option<bool>) {
+    if DARK.with(|d| d.replace(dark)) != dark {
+        info!("Desktop style preference: {:?}", dark);
+        config::changed();
+    }
+}
+
+/// Follows a preference that the desktop actually stated, the header bars too
+fn follow_desktop(dark: Option<bool>) {
+    if let Some(settings) = gtk::Settings::get_default() {
+        // what the user had set is kept, unless the desktop says dark
+        let user = USER_PREFER_DARK.with(|u| {
+            if u.get().is_none() {
+                u.set(Some(settings.get_property_gtk_application_prefer_dark_theme()));
+            }
+            u.get().unwrap_or(false)
+        });
+        let prefer_dark = dark == Some(true) || user;
+        if settings.get_property_gtk_application_prefer_dark_theme() != prefer_dark {
+            settings.set_property_gtk_application_prefer_dark_theme(prefer_dark);
+        }
+    }
+    set_dark(dark);
+}
+
+pub fn monospace_font() -> Option<String> {
+    MONOSPACE.with(|m| m.borrow().clone())
+}
+
+fn set_monospace_font(settings: &gio::Settings) {
+    let desc = settings.get_string("monospace-font-name").map(String::from).filter(|s| !s.is_empty());
+    info!("Desktop monospace font: {:?}", desc);
+    MONOSPACE.with(|m| *m.borrow_mut() = desc);
+    config::changed();
+}
+
+/// Interprets the portal's color-scheme: 1 is dark, 2 is light,
+/// and 0 (no preference) gets the usual light style
+fn from_portal(value: u32) -> Option<bool> {
+    match value {
+        1 => Some(true),
+        0 | 2 => Some(false),
+        _ => None,
+    }
+}
+
+/// Reads the color-scheme setting, which is what the portal's value comes from on GNOME
+fn from_interface(settings: &gio::Settings) -> Option<bool> {
+    match settings.get_string("color-scheme").as_ref().map(|s| &**s) {
+        Some("prefer-dark") => Some(true),
+        Some("prefer-light") | Some("default") => Some(false),
+        _ => None,
+    }
+}
+
+/// Guesses from the GTK theme, which comes from XSETTINGS or settings.ini elsewhere.
+///
+/// Not from prefer-dark, that's what follow_desktop writes.
+fn from_gtk(settings: &gtk::Settings) -> Option<bool> {
+    settings.get_property_gtk_theme_name()
+        .map(|name| name.to_lowercase().ends_with("-dark") || name.to_lowercase().ends_with(":dark"))
+}
+
+/// Unpacks a `u`, however many variants it's wrapped in (Read wraps it in two)
+unsafe fn variant_u32(mut value: *mut glib_sys::GVariant) -> Option<u32> {
+    glib_sys::g_variant_ref(value);
+    loop {
+        let type_string = CStr::from_ptr(glib_sys::g_variant_get_type_string(value)).to_bytes();
+        let inner = match type_string {
+            b"u" => {
+                let result = glib_sys::g_variant_get_uint32(value);
+                glib_sys::g_variant_unref(value);
+                return Some(result);
+            },
+            b"v" => glib_sys::g_variant_get_variant(value),
+            b"(v)" => glib_sys::g_variant_get_child_value(value, 0),
+            _ => {
+                glib_sys::g_variant_unref(value);
+                return None;
+            },
+        };
+        glib_sys::g_variant_unref(value);
+        value = inner;
+    }
+}
+
+unsafe fn variant_str<'a>(value: *mut glib_sys::GVariant) -> &'a str {
+    CStr::from_ptr(glib_sys::g_variant_get_string(value, ptr::null_mut())).to_str().unwrap_or("")
+}
+
+/// Handles the portal's SettingChanged(namespace, key, value) signal
+unsafe extern "C" fn portal_signal(_proxy: *mut gio_sys::GDBusProxy, _sender: *mut c_char, signal: *mut c_char,
+                                   params: *mut glib_sys::GVariant, _data: glib_sys::gpointer) {
+    if CStr::from_ptr(signal).to_bytes() != b"SettingChanged" {
+        return;
+    }
+    let namespace = glib_sys::g_variant_get_child_value(params, 0);
+    let key = glib_sys::g_variant_get_child_value(params, 1);
+    let value = glib_sys::g_variant_get_child_value(params, 2);
+    if variant_str(namespace) == PORTAL_NAMESPACE && variant_str(key) == PORTAL_KEY {
+        if let Some(value) = variant_u32(value) {
+            follow_desktop(from_portal(value));
+        }
+    }
+    glib_sys::g_variant_unref(namespace);
+    glib_sys::g_variant_unref(key);
+    glib_sys::g_variant_unref(value);
+}
+
+/// Reads the color scheme from the settings portal and starts following it,
+/// returns None if there's no portal or it doesn't have the setting
+fn watch_portal() -> Option<u32> {
+    // gio 0.5 has no D-Bus bindings
+    unsafe {
+        let mut error = ptr::null_mut();
+        let proxy = gio_sys::g_dbus_proxy_new_for_bus_sync(
+            gio_sys::G_BUS_TYPE_SESSION,
+            gio_sys::G_DBUS_PROXY_FLAGS_DO_NOT_LOAD_PROPERTIES,
+            ptr::null_mut(),
+            b"org.freedesktop.portal.Desktop\0".as_ptr() as *const _,
+            b"/org/freedesktop/portal/desktop\0".as_ptr() as *const _,
+            b"org.freedesktop.portal.Settings\0".as_ptr() as *const _,
+            ptr::null_mut(),
+            &mut error,
+        );
+        if proxy.is_null() {
+            info!("No settings portal: {}", CStr::from_ptr((*error).message).to_string_lossy());
+            glib_sys::g_error_free(error);
+            return None;
+        }
+        let namespace = CString::new(PORTAL_NAMESPACE).expect("namespace");
+        let key = CString::new(PORTAL_KEY).expect("key");
+        let args = [glib_sys::g_variant_new_string(namespace.as_ptr()), glib_sys::g_variant_new_string(key.as_ptr())];
+        let result = gio_sys::g_dbus_proxy_call_sync(
+            proxy,
+            b"Read\0".as_ptr() as *const _,
+            glib_sys::g_variant_new_tuple(args.as_ptr(), args.len()),
+            gio_sys::G_DBUS_CALL_FLAGS_NONE,
+            1000,
+            ptr::null_mut(),
+            &mut error,
+        );
+        if result.is_null() {
+            info!("The settings portal has no color scheme: {}", CStr::from_ptr((*error).message).to_string_lossy());
+            glib_sys::g_error_free(error);
+            gobject_sys::g_object_unref(proxy as *mut _);
+            return None;
+        }
+        let value = variant_u32(result);
+        glib_sys::g_variant_unref(result);
+        let handler: unsafe extern "C" fn(_, _, _, _, _) = portal_signal;
+        gobject_sys::g_signal_connect_data(
+            proxy as *mut _,
+            b"g-signal\0".as_ptr() as *const _,
+            Some(std::mem::transmute(handler)),
+            ptr::null_mut(),
+            None,
+            0,
+        );
+        PORTAL.with(|p| p.set(proxy));
+        value
+    }
+}
+
+/// Starts following the desktop's monospace font and dark/light style preference
+pub fn watch() {
+    if let Some(settings) = desktop_interface("monospace-font-name") {
+        set_monospace_font(&settings);
+        settings.connect_changed(|settings, key| {
+            if key == "monospace-font-name" {
+                set_monospace_font(settings);
+            }
+        });
+        WATCHED.with(|w| w.borrow_mut().push(settings));
+    }
+    if let Some(value) = watch_portal() {
+        follow_desktop(from_portal(value));
+    } else if let Some(settings) = desktop_interface("color-scheme") {
+        follow_desktop(from_interface(&settings));
+        settings.connect_changed(|settings, key| {
+            if key == "color-scheme" {
+                follow_desktop(from_interface(settings));
+            }
+        });
+        WATCHED.with(|w| w.borrow_mut().push(settings));
+    } else if let Some(settings) = gtk::Settings::get_default() {
+        // only a guess, the user's prefer-dark is left alone
+        set_dark(from_gtk(&settings));
+        settings.connect_property_gtk_theme_name_notify(|settings| set_dark(from_gtk(settings)));
+    }
+}
@@ -0,0 +1,128 @@
+use std::path::{Path, PathBuf};
+
+pub const USAGE: &str = "Usage: galacritty [OPTIONS] [-e COMMAND [ARGS...]]
+
+Options:
+  -e, --command COMMAND...       Run COMMAND instead of the shell (consumes the remaining arguments)
+  -d, --working-directory DIR    Start in DIR
+  -t, --title TITLE              Initial window title
+  -p, --profile NAME             Use the settings of a profile
+      --hold                     Keep the terminal open after the command exits
+      --class CLASS              Window class (WM_CLASS)
+  -h, --help                     Show this message";
+
+/// What to open a window with, from the command line
+#[derive(Default, Debug, Clone)]
+pub struct Options {
+    pub command: Vec<String>,
+    pub working_dir: Option<PathBuf>,
+    /// The directory galacritty was started in, used when neither the options nor the profile set one
+    pub cwd: Option<PathBuf>,
+    pub title: Option<String>,
+    /// Name of the profile, replaced with its ID once it's found
+    pub profile: Option<String>,
+    pub hold: bool,
+    pub class: Option<String>,
+    pub help: bool,
+}
+
+impl Options {
+    /// Makes a relative working directory absolute, relative to the invoking process's directory
+    pub fn resolve_working_dir(&mut self, cwd: Option<&Path>) {
+        if let Some(cwd) = cwd {
+            // joining an absolute path replaces cwd
+            self.working_dir = self.working_dir.take().map(|dir| cwd.join(dir));
+            self.cwd = Some(cwd.to_owned());
+        }
+    }
+}
+
+/// Parses the arguments, not including the program name
+pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let (name, inline_value) = match arg.find('=') {
+            Some(i) if arg.starts_with("--") => (arg[..i].to_owned(), Some(arg[i + 1..].to_owned())),
+            _ => (arg.clone(), None),
+        };
+        match &*name {
+            "-e" | "--command" | "-x" | "--execute" => {
+                if let Some(first) = inline_value {
+                    options.command.push(first);
+                }
+                options.command.extend(args.by_ref());
+                if options.command.is_empty() {
+                    return Err(format!("{} needs a command", name));
+                }
+            },
+            "-d" | "--working-directory" => options.working_dir = Some(PathBuf::from(value(&name, inline_value, &mut args)?)),
+            "-t" | "--title" => options.title = Some(value(&name, inline_value, &mut args)?),
+            "-p" | "--profile" => options.profile = Some(value(&name, inline_value, &mut args)?),
+            "--class" => options.class = Some(value(&name, inline_value, &mut args)?),
+            "--hold" => options.hold = true,
+            "-h" | "--help" => options.help = true,
+            _ => return Err(format!("Unknown option {}", arg)),
+        }
+    }
+    Ok(options)
+}
+
+fn value<I: Iterator<Item = String>>(name: &str, inline_value: Option<String>, args: &mut I) -> Result<String, String> {
+    inline_value.or_else(|| args.next()).ok_or_else(|| format!("{} needs a value", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<Options, String> {
+        parse(args.iter().map(|&a| a.to_owned()))
+    }
+
+    #[test]
+    fn command_takes_the_rest() {
+        let options = parse_args(&["--hold", "-e", "vim", "-d", "--hold", "file"]).unwrap();
+        assert_eq!(options.command, vec!["vim", "-d", "--hold", "file"]);
+        assert!(options.hold);
+        assert_eq!(options.working_dir, None);
+
+        let options = parse_args(&["--command=ls", "-l"]).unwrap();
+        assert_eq!(options.command, vec!["ls", "-l"]);
+        let options = parse_args(&["-x", "top"]).unwrap();
+        assert_eq!(options.command, vec!["top"]);
+    }
+
+    #[test]
+    fn options_with_values() {
+        let options = parse_args(&["--working-directory", "/tmp", "--profile=Work", "--class", "term", "-t", "Title"]).unwrap();
+        assert_eq!(options.working_dir, Some(PathBuf::from("/tmp")));
+        assert_eq!(options.profile, Some("Work".to_owned()));
+        assert_eq!(options.class, Some("term".to_owned()));
+        assert_eq!(options.title, Some("Title".to_owned()));
+        assert!(!options.hold);
+        assert!(options.command.is_empty());
+
+        let options = parse_args(&["-d", "src", "-p", "Default", "--class=a=b"]).unwrap();
+        assert_eq!(options.working_dir, Some(PathBuf::from("src")));
+        assert_eq!(options.profile, Some("Default".to_owned()));
+        assert_eq!(options.class, Some("a=b".to_owned()));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse_args(&["--frobnicate"]).unwrap_err(), "Unknown option --frobnicate");
+        assert_eq!(parse_args(&["--title=x", "-q"]).unwrap_err(), "Unknown option -q");
+        assert_eq!(parse_args(&["--profile"]).unwrap_err(), "--profile needs a value");
+        assert_eq!(parse_args(&["-d"]).unwrap_err(), "-d needs a value");
+        assert_eq!(parse_args(&["-e"]).unwrap_err(), "-e needs a command");
+    }
+
+    #[test]
+    fn relative_working_directory() {
+        let mut options = parse_args(&["-d", "src"]).unwrap();
+        options.resolve_working_dir(Some(Path::new("/home/user")));
+        assert_eq!(options.working_dir, Some(PathBuf::from("/home/user/src")));
+        assert_eq!(options.cwd, Some(PathBuf::from("/home/user")));
+    }
+}
@@ -0,0 +1,379 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+
+use glib;
+use serde_yaml::{self, Value};
+
+use alacritty::config::Colors;
+
+/// Background, foreground, then the 8 normal and 8 bright colors
+type Palette = [u32; 18];
+
+const TANGO: [u32; 16] = [
+    0x2e3436, 0xcc0000, 0x4e9a06, 0xc4a000, 0x3465a4, 0x75507b, 0x06989a, 0xd3d7cf,
+    0x555753, 0xef2929, 0x8ae234, 0xfce94f, 0x729fcf, 0xad7fa8, 0x34e2e2, 0xeeeeec,
+];
+
+const SOLARIZED: [u32; 16] = [
+    0x073642, 0xdc322f, 0x859900, 0xb58900, 0x268bd2, 0xd33682, 0x2aa198, 0xeee8d5,
+    0x002b36, 0xcb4b16, 0x586e75, 0x657b83, 0x839496, 0x6c71c4, 0x93a1a1, 0xfdf6e3,
+];
+
+const GNOME: [u32; 16] = [
+    0x171421, 0xc01c28, 0x26a269, 0xa2734c, 0x12488b, 0xa347ba, 0x2aa1b3, 0xd0cfcc,
+    0x5e5c64, 0xf66151, 0x33d17a, 0xe9ad0c, 0x2a7bde, 0xc061cb, 0x33c7de, 0xffffff,
+];
+
+const GRUVBOX_DARK: [u32; 16] = [
+    0x282828, 0xcc241d, 0x98971a, 0xd79921, 0x458588, 0xb16286, 0x689d6a, 0xa89984,
+    0x928374, 0xfb4934, 0xb8bb26, 0xfabd2f, 0x83a598, 0xd3869b, 0x8ec07c, 0xebdbb2,
+];
+
+const GRUVBOX_LIGHT: [u32; 16] = [
+    0xfbf1c7, 0xcc241d, 0x98971a, 0xd79921, 0x458588, 0xb16286, 0x689d6a, 0x7c6f64,
+    0x928374, 0x9d0006, 0x79740e, 0xb57614, 0x076678, 0x8f3f71, 0x427b58, 0x3c3836,
+];
+
+fn palette(background: u32, foreground: u32, colors: [u32; 16]) -> Palette {
+    let mut result = [0; 18];
+    result[0] = background;
+    result[1] = foreground;
+    result[2..].copy_from_slice(&colors);
+    result
+}
+
+fn builtin() -> Vec<(&'static str, Palette)> {
+    vec![
+        ("GNOME Dark", palette(0x171421, 0xd0cfcc, GNOME)),
+        ("GNOME Light", palette(0xffffff, 0x171421, GNOME)),
+        ("Gruvbox Dark", palette(0x282828, 0xebdbb2, GRUVBOX_DARK)),
+        ("Gruvbox Light", palette(0xfbf1c7, 0x3c3836, GRUVBOX_LIGHT)),
+        ("Solarized Dark", palette(0x002b36, 0x839496, SOLARIZED)),
+        ("Solarized Light", palette(0xfdf6e3, 0x657b83, SOLARIZED)),
+        ("Tango Dark", palette(0x2e3436, 0xd3d7cf, TANGO)),
+        ("Tango Light", palette(0xeeeeec, 0x2e3436, TANGO)),
+    ]
+}
+
+/// Writes a palette as the `colors` section of an Alacritty config
+fn to_alacritty(palette: &Palette) -> String {
+    const NAMES: [&str; 8] = ["black", "red", "green", "yellow", "blue", "magenta", "cyan", "white"];
+    let mut yaml = format!("primary:\n  background: '0x{:06x}'\n  foreground: '0x{:06x}'\n", palette[0], palette[1]);
+    for (section, colors) in [("normal", &palette[2..10]), ("bright", &palette[10..18])].iter() {
+        yaml.push_str(&format!("{}:\n", section));
+        for (name, color) in NAMES.iter().zip(colors.iter()) {
+            yaml.push_str(&format!("  {}: '0x{:06x}'\n", name, color));
+        }
+    }
+    yaml
+}
+
+/// Where imported color schemes are stored, as Alacritty YAML
+fn dir() -> PathBuf {
+    glib::get_user_config_dir().unwrap_or_else(|| PathBuf::from(".config"))
+        .join("galacritty").join("colors")
+}
+
+fn imported() -> Vec<(String, PathBuf)> {
+    let mut result = fs::read_dir(dir()).into_iter()
+        .flat_map(|entries| entries.filter_map(Result::ok))
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().map(|e| e == "yml").unwrap_or(false))
+        .filter_map(|path| Some((path.file_stem()?.to_string_lossy().into_owned(), path.clone())))
+        .collect::<Vec<_>>();
+    result.sort();
+    result
+}
+
+/// Names of the built-in and imported color schemes
+pub fn names() -> Vec<String> {
+    let mut names = builtin().into_iter().map(|(name, _)| name.to_owned()).collect::<Vec<_>>();
+    // built-in ones take precedence
+    let imported = imported().into_iter().map(|(name, _)| name).filter(|n| !names.contains(n)).collect::<Vec<_>>();
+    names.extend(imported);
+    names
+}
+
+/// Parses the `colors` section of an Alacritty config, or a whole config
+pub fn from_alacritty(yaml: &str) -> Result<Colors, String> {
+    let value: Value = serde_yaml::from_str(yaml).map_err(|e| e.to_string())?;
+    let colors = match value.get("colors") {
+        Some(colors) => colors.clone(),
+        None => value,
+    };
+    serde_yaml::from_value(colors).map_err(|e| e.to_string())
+}
+
+/// Returns a built-in or imported color scheme
+pub fn by_name(name: &str) -> Option<Colors> {
+    if let Some((_, palette)) = builtin().into_iter().find(|&(n, _)| n == name) {
+        return Some(from_alacritty(&to_alacritty(&palette)).expect("built-in color scheme"));
+    }
+    let (_, path) = imported().into_iter().find(|(n, _)| n == name)?;
+    match fs::read_to_string(&path).map_err(|e| e.to_string()).and_then(|yaml| from_alacritty(&yaml)) {
+        Ok(colors) => Some(colors),
+        Err(err) => {
+            warn!("Could not load color scheme {:?}: {}", path, err);
+            None
+        },
+    }
+}
+
+/// Parses colors like #rrggbb, #rgb, 0xrrggbb and rgb:rr/gg/bb
+fn parse_color(s: &str) -> Option<u32> {
+    let s = s.trim().trim_matches(|c| c == '"' || c == '\'');
+    if s.starts_with("rgb:") {
+        let parts = s[4..].split('/').collect::<Vec<_>>();
+        if parts.len() != 3 {
+            return None;
+        }
+        // each component can have 1 to 4 hex digits, only the most significant byte matters
+        return parts.iter().try_fold(0, |acc, part| {
+            if part.is_empty() || part.len() > 4 {
+                return None;
+            }
+            let value = u32::from_str_radix(part, 16).ok()?;
+            let bits = 4 * part.len() as u32;
+            Some(acc << 8 | (value * 255 / ((1 << bits) - 1)))
+        });
+    }
+    let hex = if s.starts_with('#') { &s[1..] } else if s.starts_with("0x") { &s[2..] } else { s };
+    match hex.len() {
+        6 => u32::from_str_radix(hex, 16).ok(),
+        3 => u32::from_str_radix(hex, 16).ok().map(|v| {
+            let (r, g, b) = ((v >> 8) & 0xf, (v >> 4) & 0xf, v & 0xf);
+            (r * 0x11) << 16 | (g * 0x11) << 8 | b * 0x11
+        }),
+        _ => None,
+    }
+}
+
+/// Reads a base16 scheme (base00 to base0F), mapped like base16-shell does
+fn from_base16(yaml: &str) -> Result<Palette, String> {
+    let value: HashMap<String, Value> = serde_yaml::from_str(yaml).map_err(|e| e.to_string())?;
+    let base = |n: usize| {
+        let key = format!("base{:02X}", n);
+        value.get(&key).and_then(|v| v.as_str()).and_then(parse_color)
+            .ok_or_else(|| format!("{} is missing", key))
+    };
+    let mut result = [0; 18];
+    for (i, &n) in [0x0, 0x5, 0x0, 0x8, 0xB, 0xA, 0xD, 0xE, 0xC, 0x5,
+                    0x3, 0x8, 0xB, 0xA, 0xD, 0xE, 0xC, 0x7].iter().enumerate() {
+        result[i] = base(n)?;
+    }
+    Ok(result)
+}
+
+/// Reads an X resources file with colors for terminals like xterm and urxvt
+fn from_xresources(text: &str) -> Result<Palette, String> {
+    let mut defines = HashMap::new();
+    let mut resources = HashMap::new();
+    for line in text.lines() {
+        let line = line.trim();
+        if line.starts_with("#define") {
+            let mut parts = line.split_whitespace().skip(1);
+            if let (Some(name), Some(value)) = (parts.next(), parts.next()) {
+                defines.insert(name.to_owned(), value.to_owned());
+            }
+            continue;
+        }
+        if line.starts_with('!') || line.starts_with('#') {
+            continue;
+        }
+        let mut parts = line.splitn(2, ':');
+        if let (Some(key), Some(value)) = (parts.next(), parts.next()) {
+            // "*.color0", "URxvt*background" and so on, only the last component matters
+            let key = key.trim().rsplit(|c| c == '.' || c == '*').next().unwrap_or("").to_lowercase();
+            let value = value.trim();
+            let value = defines.get(value).map(|v| &**v).unwrap_or(value);
+            if let Some(color) = parse_color(value) {
+                resources.insert(key, color);
+            }
+        }
+    }
+    let get = |key: &str| resources.get(key).cloned().ok_or_else(|| format!("{} is missing", key));
+    let mut result = [0; 18];
+    result[0] = get("background")?;
+    result[1] = get("foreground")?;
+    for i in 0..16 {
+        result[i + 2] = get(&format!("color{}", i))?;
+    }
+    Ok(result)
+}
+
+/// Reads an iTerm2 .itermcolors property list
+fn from_itermcolors(xml: &str) -> Result<Palette, String> {
+    // just enough of a plist parser: the top level dict has color names as keys and
+    // dicts of component names to reals as values
+    let mut colors = HashMap::new();
+    let mut name = None;
+    let mut component = None;
+    let mut depth = 0;
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        let end = rest[start..].find('>').ok_or("Unterminated tag")? + start;
+        let tag = &rest[start + 1..end];
+        let after = &rest[end + 1..];
+        let text = &after[..after.find('<').unwrap_or(after.len())];
+        match tag {
+            "dict" => depth += 1,
+            "/dict" => depth -= 1,
+            "key" if depth == 1 => name = Some(text.trim().to_owned()),
+            "key" if depth == 2 => component = Some(text.trim().to_owned()),
+            "real" | "integer" if depth == 2 => {
+                if let (Some(name), Some(component)) = (&name, &component) {
+                    let value = text.trim().parse::<f64>().map_err(|e| e.to_string())?;
+                    let shift = match &**component {
+                        "Red Component" => 16,
+                        "Green Component" => 8,
+                        "Blue Component" => 0,
+                        _ => { rest = after; continue },
+                    };
+                    let byte = (value.max(0.0).min(1.0) * 255.0).round() as u32;
+                    *colors.entry(name.clone()).or_insert(0) |= byte << shift;
+                }
+            },
+            _ => (),
+        }
+        rest = after;
+    }
+    let get = |key: &str| colors.get(key).cloned().ok_or_else(|| format!("{} is missing", key));
+    let mut result = [0; 18];
+    result[0] = get("Background Color")?;
+    result[1] = get("Foreground Color")?;
+    for i in 0..16 {
+        result[i + 2] = get(&format!("Ansi {} Color", i))?;
+    }
+    Ok(result)
+}
+
+/// Converts a color scheme in any of the supported formats to Alacritty YAML
+fn convert(text: &str) -> Result<String, String> {
+    if text.contains("<plist") {
+        return from_itermcolors(text).map(|p| to_alacritty(&p));
+    }
+    if let Ok(Value::Mapping(map)) = serde_yaml::from_str::<Value>(text) {
+        if map.contains_key(&Value::String("base00".to_owned())) {
+            return from_base16(text).map(|p| to_alacritty(&p));
+        }
+        if map.contains_key(&Value::String("colors".to_owned())) || map.contains_key(&Value::String("primary".to_owned())) {
+            // validate, but keep whatever else is in there, like the cursor colors
+            from_alacritty(text)?;
+            let value = serde_yaml::from_str::<Value>(text).map_err(|e| e.to_string())?;
+            let colors = value.get("colors").cloned().unwrap_or(value);
+            return serde_yaml::to_string(&colors).map_err(|e| e.to_string());
+        }
+    }
+    from_xresources(text).map(|p| to_alacritty(&p))
+}
+
+/// Imports a color scheme from an Alacritty config, a base16 scheme, an .itermcolors file
+/// or X resources, returning the name it's available under
+pub fn import(path: &Path) -> Result<String, String> {
+    let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let yaml = convert(&text)?;
+    let mut name = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_else(|| "Imported".to_owned());
+    // built-in ones take precedence, one with the same name could never be selected
+    if builtin().iter().any(|&(n, _)| n == name) {
+        name += " (imported)";
+    }
+    let dir = dir();
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    fs::write(dir.join(format!("{}.yml", name)), yaml).map_err(|e| e.to_string())?;
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A palette where each of the 16 colors has its index in every digit
+    fn gray(n: usize) -> u32 {
+        n as u32 * 0x111111
+    }
+
+    #[test]
+    fn color_formats() {
+        assert_eq!(parse_color("#1a2b3c"), Some(0x1a2b3c));
+        assert_eq!(parse_color("0x1a2b3c"), Some(0x1a2b3c));
+        assert_eq!(parse_color(" '#1A2B3C' "), Some(0x1a2b3c));
+        assert_eq!(parse_color("#f80"), Some(0xff8800));
+        assert_eq!(parse_color("rgb:ff/80/00"), Some(0xff8000));
+        assert_eq!(parse_color("rgb:f/8/0"), Some(0xff8800));
+        assert_eq!(parse_color("rgb:ffff/0000/8080"), Some(0xff0080));
+    }
+
+    #[test]
+    fn malformed_colors() {
+        assert_eq!(parse_color("#ff"), None);
+        assert_eq!(parse_color("#gggggg"), None);
+        assert_eq!(parse_color("rgb:ff/ff"), None);
+        assert_eq!(parse_color("rgb:/ff/ff"), None);
+        assert_eq!(parse_color("rgb:fffff/ff/ff"), None);
+        assert_eq!(parse_color("red"), None);
+    }
+
+    #[test]
+    fn base16() {
+        let mut yaml = "scheme: \"Grays\"\nauthor: \"Nobody\"\n".to_owned();
+        for n in 0..16 {
+            yaml.push_str(&format!("base{:02X}: \"{:06x}\"\n", n, gray(n)));
+        }
+        let palette = from_base16(&yaml).unwrap();
+        let expected = [0x0, 0x5, 0x0, 0x8, 0xB, 0xA, 0xD, 0xE, 0xC, 0x5, 0x3, 0x8, 0xB, 0xA, 0xD, 0xE, 0xC, 0x7];
+        assert_eq!(palette.to_vec(), expected.iter().map(|&n| gray(n)).collect::<Vec<_>>());
+        assert_eq!(convert(&yaml), Ok(to_alacritty(&palette)));
+
+        let missing = yaml.replace("base07", "baseXX");
+        assert_eq!(from_base16(&missing), Err("base07 is missing".to_owned()));
+    }
+
+    #[test]
+    fn xresources() {
+        let mut text = "! comment\n#define bg #101010\n*background: bg\n*.foreground: rgb:ee/ee/ee\n".to_owned();
+        for n in 0..16 {
+            let prefix = if n % 2 == 0 { "*" } else { "*." };
+            text.push_str(&format!("{}color{}: #{:06x}\n", prefix, n, gray(n)));
+        }
+        let palette = from_xresources(&text).unwrap();
+        assert_eq!(palette[0], 0x101010);
+        assert_eq!(palette[1], 0xeeeeee);
+        assert_eq!(palette[2..].to_vec(), (0..16).map(gray).collect::<Vec<_>>());
+        assert_eq!(convert(&text), Ok(to_alacritty(&palette)));
+
+        let missing = text.replace("*.color15", "*.colour15");
+        assert_eq!(from_xresources(&missing), Err("color15 is missing".to_owned()));
+    }
+
+    #[test]
+    fn itermcolors() {
+        let color = |name: &str, r: f64, g: f64, b: f64| format!(
+            "<key>{}</key>\n<dict>\n<key>Alpha Component</key><real>1</real>\n\
+             <key>Blue Component</key><real>{}</real>\n<key>Color Space</key><string>sRGB</string>\n\
+             <key>Green Component</key><real>{}</real>\n<key>Red Component</key><real>{}</real>\n</dict>\n",
+            name, b, g, r);
+        let mut xml = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<plist version=\"1.0\">\n<dict>\n".to_owned();
+        xml.push_str(&color("Background Color", 0.0, 0.0, 0.5));
+        xml.push_str(&color("Foreground Color", 1.0, 1.0, 1.0));
+        for n in 0..16 {
+            let v = n as f64 / 15.0;
+            xml.push_str(&color(&format!("Ansi {} Color", n), v, v, v));
+        }
+        xml.push_str("</dict>\n</plist>\n");
+        let palette = from_itermcolors(&xml).unwrap();
+        assert_eq!(palette[0], 0x000080);
+        assert_eq!(palette[1], 0xffffff);
+        assert_eq!(palette[2..].to_vec(), (0..16).map(gray).collect::<Vec<_>>());
+        assert_eq!(convert(&xml), Ok(to_alacritty(&palette)));
+    }
+
+    #[test]
+    fn malformed_files() {
+        assert!(from_itermcolors("<plist><dict").is_err());
+        assert!(from_itermcolors("<plist><dict><key>Background Color</key><dict>\
+                                  <key>Red Component</key><real>red</real></dict></dict></plist>").is_err());
+        assert_eq!(from_xresources("*background: #000000"), Err("foreground is missing".to_owned()));
+        assert!(convert("not a color scheme").is_err());
+    }
+}
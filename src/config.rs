@@ -0,0 +1,186 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::cell::{Cell, RefCell};
+
+use glib;
+use gio;
+use gio::prelude::*;
+use gtk;
+use gtk::prelude::*;
+use serde_yaml::{self, Value};
+
+use alacritty::config::{self, Config};
+
+use crate::appearance;
+use crate::font;
+use crate::prefs;
+use crate::profiles;
+use crate::widget;
+
+thread_local!{
+    /// As loaded from the file, without the preferences applied
+    static FILE_CONFIG: RefCell<Config> = RefCell::new(Config::default());
+    /// Whether the file has a font, otherwise the desktop's monospace font is used
+    static FILE_HAS_FONT: Cell<bool> = Cell::new(false);
+    /// Bumped on every successful reload, terminals compare it with the one they were configured with
+    static GENERATION: Cell<usize> = Cell::new(0);
+    static ERROR: RefCell<Option<String>> = RefCell::new(None);
+    /// Info bars showing the error, one per window
+    static ERROR_BARS: RefCell<Vec<(gtk::InfoBar, gtk::Label)>> = RefCell::new(Vec::new());
+    static MONITORS: RefCell<Vec<gio::FileMonitor>> = RefCell::new(Vec::new());
+}
+
+/// The configuration files in the order of preference
+fn paths() -> Vec<PathBuf> {
+    let dir = glib::get_user_config_dir().unwrap_or_else(|| PathBuf::from(".config"));
+    vec![
+        dir.join("galacritty").join("galacritty.yml"),
+        dir.join("alacritty").join("alacritty.yml"),
+    ]
+}
+
+fn has_font(path: &Path) -> bool {
+    match fs::read_to_string(path).ok().and_then(|yaml| serde_yaml::from_str::<Value>(&yaml).ok()) {
+        Some(Value::Mapping(map)) => map.contains_key(&Value::String("font".to_owned())),
+        _ => false,
+    }
+}
+
+/// Loads the configuration and whether it sets the font
+fn load() -> Result<(Config, bool), String> {
+    let path = match paths().into_iter().find(|p| p.exists()) {
+        Some(path) => path,
+        None => return Ok((Config::default(), false)),
+    };
+    info!("Loading config from {:?}", path);
+    match Config::load_from(path.clone()) {
+        Ok(config) => Ok((config, has_font(&path))),
+        Err(config::Error::Empty) => Ok((Config::default(), false)),
+        Err(err) => Err(format!("{}: {}", path.display(), err)),
+    }
+}
+
+/// The configuration for terminals with the given profile, or none
+pub fn for_profile(profile: Option<&str>) -> Config {
+    let prefs = prefs::get();
+    let mut config = base_config();
+    prefs.apply(&mut config);
+    if let Some(id) = profile {
+        profiles::apply(id, &mut config);
+    }
+    // every glyph cache rebuild starts here
+    prefs.apply_fallback();
+    config
+}
+
+/// The configuration from the file as it is
+pub fn file_config() -> Config {
+    FILE_CONFIG.with(|c| c.borrow().clone())
+}
+
+/// The configuration file with the desktop's font, what the preferences fall back to
+pub fn base_config() -> Config {
+    let mut config = file_config();
+    if !FILE_HAS_FONT.with(|f| f.get()) {
+        if let Some(desc) = appearance::monospace_font() {
+            config.set_font(font::from_description(&desc));
+        }
+    }
+    config
+}
+
+pub fn generation() -> usize {
+    GENERATION.with(|g| g.get())
+}
+
+/// Reads the configuration file again, applying it to all terminals if it's valid
+pub fn reload() {
+    match load() {
+        Ok((config, has_font)) => {
+            FILE_CONFIG.with(|c| *c.borrow_mut() = config);
+            FILE_HAS_FONT.with(|f| f.set(has_font));
+            set_error(None);
+            changed();
+        },
+        Err(err) => {
+            warn!("Could not load config: {}", err);
+            set_error(Some(err));
+        },
+    }
+}
+
+/// Makes all terminals pick up the current configuration
+pub fn changed() {
+    GENERATION.with(|g| g.set(g.get() + 1));
+    widget::queue_draw_all();
+}
+
+/// Loads the configuration and reloads it whenever one of the files changes
+pub fn watch() {
+    reload();
+    for path in paths() {
+        let file = gio::File::new_for_path(&path);
+        match file.monitor_file(gio::FileMonitorFlags::NONE, None) {
+            Ok(monitor) => {
+                monitor.connect_changed(|_, _, _, event| {
+                    match event {
+                        gio::FileMonitorEvent::ChangesDoneHint |
+                        gio::FileMonitorEvent::Created |
+                        gio::FileMonitorEvent::Deleted |
+                        gio::FileMonitorEvent::MovedIn |
+                        gio::FileMonitorEvent::MovedOut |
+                        gio::FileMonitorEvent::Renamed => reload(),
+                        _ => (),
+                    }
+                });
+                MONITORS.with(|m| m.borrow_mut().push(monitor));
+            },
+            Err(err) => warn!("Could not watch {:?}: {}", path, err),
+        }
+    }
+}
+
+fn set_error(error: Option<String>) {
+    ERROR.with(|e| *e.borrow_mut() = error.clone());
+    ERROR_BARS.with(|bars| {
+        for (bar, label) in bars.borrow().iter() {
+            show_error(bar, label, error.as_ref().map(|e| &**e));
+        }
+    });
+}
+
+fn show_error(bar: &gtk::InfoBar, label: &gtk::Label, error: Option<&str>) {
+    match error {
+        Some(error) => {
+            label.set_text(&format!("The configuration file could not be loaded, the previous settings are still used.\n{}", error));
+            bar.show();
+        },
+        None => bar.hide(),
+    }
+}
+
+/// Creates an info bar that shows up when the configuration file is broken
+pub fn error_bar() -> gtk::InfoBar {
+    let bar = gtk::InfoBar::new();
+    bar.set_message_type(gtk::MessageType::Error);
+    bar.set_show_close_button(true);
+    // shown only when there is an error
+    bar.set_no_show_all(true);
+    bar.connect_response(|bar, _| bar.hide());
+
+    let label = gtk::Label::new(None);
+    label.set_xalign(0.0);
+    label.set_line_wrap(true);
+    label.set_selectable(true);
+    label.show();
+    if let Some(content) = bar.get_content_area() {
+        content.add(&label);
+    }
+
+    ERROR.with(|e| show_error(&bar, &label, e.borrow().as_ref().map(|e| &**e)));
+    ERROR_BARS.with(|bars| bars.borrow_mut().push((bar.clone(), label)));
+    bar.connect_destroy(|bar| {
+        ERROR_BARS.with(|bars| bars.borrow_mut().retain(|(b, _)| b != bar));
+    });
+    bar
+}
@@ -1,38 +1,313 @@
-use pango::{self, Style, Weight};
+use std::fmt;
+use std::cell::RefCell;
+use std::ffi::CString;
+use std::os::raw::{c_int, c_void};
+
+use gdk;
+use glib::translate::ToGlib;
+use pango::{self, Style, Variant};
 use pango::prelude::*;
 
 use alacritty::config::{Font, Size};
 
-/// Converts a Pango/Gtk font spec to an Alacritty one
-pub fn to_alacritty(fam: pango::FontFamily, size: i32) -> Font {
-    let size = size as f32 / pango::SCALE as f32;
-    info!("Chosen font family {:?} size {}", fam.get_name(), size);
+/// A face of a font family, as far as picking one goes
+struct Face {
+    name: Option<String>,
+    style: Style,
+    weight: i32,
+    small_caps: bool,
+}
+
+const BOLD: i32 = 700;
+const HEAVY: i32 = 900;
+
+fn faces(fam: &pango::FontFamily) -> Vec<Face> {
+    fam.list_faces().iter().filter_map(|face| {
+        let desc = face.describe()?;
+        info!("  - has face {:?} style {:?} weight {:?} variant {:?}", face.get_face_name(), desc.get_style(), desc.get_weight(), desc.get_variant());
+        Some(Face {
+            name: face.get_face_name(),
+            style: desc.get_style(),
+            weight: desc.get_weight().to_glib() as i32,
+            small_caps: desc.get_variant() != Variant::Normal,
+        })
+    }).collect()
+}
+
+/// Finds the face closest to the wanted slant and weight.
+///
+/// Italic falls back to oblique, and families without the exact weight get the
+/// nearest one (e.g. Semibold or Heavy for bold).
+fn closest_face(faces: &[Face], slanted: bool, weight: i32) -> Option<String> {
+    faces.iter()
+        .filter(|f| (f.style != Style::Normal) == slanted)
+        .min_by_key(|f| {
+            let oblique = f.style == Style::Oblique;
+            // on a tie, bold goes heavier and everything else lighter
+            let wrong_side = if weight >= BOLD { f.weight < weight } else { f.weight > weight };
+            (f.small_caps, oblique, (f.weight - weight).abs(), wrong_side)
+        })
+        .and_then(|f| f.name.clone())
+}
+
+/// Converts a Pango/Gtk font spec to an Alacritty one, keeping the chosen face
+pub fn to_alacritty(fam: pango::FontFamily, desc: &pango::FontDescription) -> Font {
+    let size = desc.get_size() as f32 / pango::SCALE as f32;
+    info!("Chosen font family {:?} size {} style {:?} weight {:?}", fam.get_name(), size, desc.get_style(), desc.get_weight());
     let mut newf = Font::default();
     newf.size = Size::new(size);
     if let Some(name) = fam.get_name() {
         newf.normal.family = name.clone();
         newf.bold.family = name.clone();
-        newf.italic.family = name;
+        newf.italic.family = name.clone();
+        newf.bold_italic.family = name;
     } else {
         warn!("You've managed to select a font family with no name, somehow.");
     }
-    // Find exact names of "Normal" "Bold" "Italic" suffixes for this family
-    for face in fam.list_faces().iter() {
-        if let Some(desc) = face.describe() {
-            info!("  - has face {:?} style {:?} weight {:?} variant {:?}", face.get_face_name(), desc.get_style(), desc.get_weight(), desc.get_variant());
-            match (desc.get_style(), desc.get_weight()) {
-                (Style::Normal, Weight::Normal) => {
-                    newf.normal.style = face.get_face_name();
-                },
-                (Style::Normal, Weight::Bold) => {
-                    newf.bold.style = face.get_face_name();
-                },
-                (Style::Italic, Weight::Normal) => {
-                    newf.italic.style = face.get_face_name();
-                },
-                _ => (),
-            }
-        }
+    let faces = faces(&fam);
+    let slanted = desc.get_style() != Style::Normal;
+    let weight = desc.get_weight().to_glib() as i32;
+    let bold_weight = if weight < BOLD { BOLD } else { HEAVY };
+    // faces that aren't found keep Alacritty's default style names
+    if let Some(style) = closest_face(&faces, slanted, weight) {
+        newf.normal.style = Some(style);
+    }
+    if let Some(style) = closest_face(&faces, slanted, bold_weight) {
+        newf.bold.style = Some(style);
+    }
+    if let Some(style) = closest_face(&faces, true, weight) {
+        newf.italic.style = Some(style);
+    }
+    if let Some(style) = closest_face(&faces, true, bold_weight) {
+        newf.bold_italic.style = Some(style);
     }
     newf
 }
+
+/// Converts a Pango font description string like "Monospace 11" to an Alacritty font
+pub fn from_description(desc: &str) -> Font {
+    let mut desc = pango::FontDescription::from_string(desc);
+    if desc.get_size() <= 0 {
+        desc.set_size(11 * pango::SCALE);
+    }
+    let family = desc.get_family().and_then(|name| {
+        gdk::pango_context_get()?.list_families().into_iter()
+            .find(|fam| fam.get_name().as_ref() == Some(&name))
+    });
+    match family {
+        Some(fam) => to_alacritty(fam, &desc),
+        None => {
+            warn!("Font family {:?} not found", desc.get_family());
+            let mut newf = Font::default();
+            newf.size = Size::new(desc.get_size() as f32 / pango::SCALE as f32);
+            newf
+        },
+    }
+}
+
+/// Converts an Alacritty font to a Pango font description string, with the face
+pub fn to_description(font: &Font) -> String {
+    match font.normal.style {
+        // Pango doesn't know all face names, but it does understand the usual ones like "Medium Italic"
+        Some(ref style) if style != "Regular" => format!("{} {} {}", font.normal.family, style, font.size.as_f32_pts()),
+        _ => format!("{} {}", font.normal.family, font.size.as_f32_pts()),
+    }
+}
+
+/// A font used instead of the others for a range of characters, e.g. icons in the private use area
+#[derive(Clone, Debug, PartialEq)]
+pub struct RangeFont {
+    pub start: char,
+    pub end: char,
+    pub family: String,
+}
+
+impl RangeFont {
+    /// Parses "U+E000-U+F8FF=Family", or "U+1F600=Family" for a single character
+    pub fn parse(s: &str) -> Option<RangeFont> {
+        let mut parts = s.trim().splitn(2, '=');
+        let (range, family) = (parts.next()?.trim(), parts.next()?.trim());
+        if family.is_empty() {
+            return None;
+        }
+        let codepoint = |s: &str| {
+            let s = s.trim();
+            let hex = if s.starts_with("U+") || s.starts_with("u+") { &s[2..] } else { s };
+            u32::from_str_radix(hex, 16).ok().and_then(::std::char::from_u32)
+        };
+        let mut ends = range.splitn(2, '-');
+        let start = codepoint(ends.next()?)?;
+        let end = match ends.next() {
+            Some(end) => codepoint(end)?,
+            None => start,
+        };
+        if end < start {
+            return None;
+        }
+        Some(RangeFont { start, end, family: family.to_owned() })
+    }
+}
+
+impl fmt::Display for RangeFont {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.start == self.end {
+            write!(f, "U+{:04X}={}", self.start as u32, self.family)
+        } else {
+            write!(f, "U+{:04X}-U+{:04X}={}", self.start as u32, self.end as u32, self.family)
+        }
+    }
+}
+
+// Alacritty's glyph cache asks fontconfig for a font with each character the
+// main font doesn't have, so that's where the fallbacks go
+#[link(name = "fontconfig")]
+extern "C" {
+    fn FcInitLoadConfigAndFonts() -> *mut c_void;
+    fn FcConfigParseAndLoadFromMemory(config: *mut c_void, buffer: *const u8, complain: c_int) -> c_int;
+    fn FcConfigSetCurrent(config: *mut c_void) -> c_int;
+    fn FcConfigDestroy(config: *mut c_void);
+    fn FcGetVersion() -> c_int;
+}
+
+/// The first fontconfig version where FcConfigSetCurrent takes its own reference
+const FC_SET_CURRENT_REFERENCES: c_int = 21301;
+
+thread_local!{
+    /// The fallback fonts in the current fontconfig configuration
+    static APPLIED: RefCell<(Vec<String>, Vec<RangeFont>)> = RefCell::new((Vec::new(), Vec::new()));
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// A rule that puts the family first when looking for a font with a character in the range.
+///
+/// Alacritty looks for those with a pattern that has only the character and the size in it,
+/// no other lookup has a character set. The test checks that taking the range out of the
+/// pattern's character set changes it.
+fn range_rule(start: u32, end: u32, families: &[&str]) -> String {
+    let families = families.iter().map(|f| format!("<string>{}</string>", escape_xml(f))).collect::<String>();
+    format!(concat!(
+        "  <match target=\"pattern\">\n",
+        "    <test name=\"charset\" compare=\"not_eq\"><minus><name>charset</name>",
+        "<charset><range><int>{}</int><int>{}</int></range></charset></minus></test>\n",
+        "    <edit name=\"family\" mode=\"prepend_first\" binding=\"strong\">{}</edit>\n",
+        "  </match>\n"), start, end, families)
+}
+
+fn fallback_rules(fallback: &[String], ranges: &[RangeFont]) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\"?>\n<!DOCTYPE fontconfig SYSTEM \"fonts.dtd\">\n<fontconfig>\n");
+    if !fallback.is_empty() {
+        xml += &range_rule(0, 0x10ffff, &fallback.iter().map(|f| &**f).collect::<Vec<_>>());
+    }
+    // the later rules prepend in front of the earlier ones
+    for range in ranges {
+        xml += &range_rule(range.start as u32, range.end as u32, &[&range.family]);
+    }
+    xml += "</fontconfig>\n";
+    xml
+}
+
+/// Sets the families that the glyph cache tries, in order, for characters missing
+/// from the main font, and the ones it tries first for specific ranges.
+///
+/// This is for the whole process, and takes effect when the glyph caches are rebuilt.
+pub fn set_fallback(fallback: &[String], ranges: &[RangeFont]) {
+    let unchanged = APPLIED.with(|a| {
+        let applied = a.borrow();
+        applied.0 == fallback && applied.1 == ranges
+    });
+    if unchanged {
+        return;
+    }
+    APPLIED.with(|a| *a.borrow_mut() = (fallback.to_vec(), ranges.to_vec()));
+    let xml = CString::new(fallback_rules(fallback, ranges)).unwrap_or_default();
+    // a fresh configuration, so the rules for the previous fallbacks are gone, fontconfig
+    // releases the previous one when it's replaced
+    unsafe {
+        let config = FcInitLoadConfigAndFonts();
+        if config.is_null() {
+            warn!("Could not load the fontconfig configuration for the fallback fonts");
+            return;
+        }
+        if FcConfigParseAndLoadFromMemory(config, xml.as_ptr() as *const u8, 1) == 0 {
+            warn!("Could not apply the fallback fonts");
+            FcConfigDestroy(config);
+            return;
+        }
+        let set = FcConfigSetCurrent(config) != 0;
+        if !set {
+            warn!("Could not switch to the configuration with the fallback fonts");
+        }
+        // before 2.13.1 the current configuration took over our reference
+        if !set || FcGetVersion() >= FC_SET_CURRENT_REFERENCES {
+            FcConfigDestroy(config);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: char, end: char, family: &str) -> RangeFont {
+        RangeFont { start, end, family: family.to_owned() }
+    }
+
+    #[test]
+    fn parse_ranges() {
+        assert_eq!(RangeFont::parse("U+E000-U+F8FF=Symbols Nerd Font"), Some(range('\u{e000}', '\u{f8ff}', "Symbols Nerd Font")));
+        assert_eq!(RangeFont::parse(" u+1f600 = Noto Color Emoji "), Some(range('\u{1f600}', '\u{1f600}', "Noto Color Emoji")));
+        assert_eq!(RangeFont::parse("2500-257f=DejaVu Sans Mono"), Some(range('\u{2500}', '\u{257f}', "DejaVu Sans Mono")));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(RangeFont::parse("U+E000"), None);
+        assert_eq!(RangeFont::parse("U+E000="), None);
+        assert_eq!(RangeFont::parse("=Family"), None);
+        assert_eq!(RangeFont::parse("U+F8FF-U+E000=Family"), None);
+        assert_eq!(RangeFont::parse("U+D800=Family"), None);
+        assert_eq!(RangeFont::parse("U+110000=Family"), None);
+        assert_eq!(RangeFont::parse("U+E000-=Family"), None);
+        assert_eq!(RangeFont::parse("smile=Family"), None);
+    }
+
+    #[test]
+    fn display_round_trip() {
+        let single = range('A', 'A', "Family");
+        assert_eq!(single.to_string(), "U+0041=Family");
+        let wide = range('\u{e000}', '\u{10fffd}', "Some Family");
+        assert_eq!(wide.to_string(), "U+E000-U+10FFFD=Some Family");
+        for font in &[single, wide] {
+            assert_eq!(RangeFont::parse(&font.to_string()).as_ref(), Some(font));
+        }
+    }
+
+    #[test]
+    fn no_rules() {
+        assert_eq!(fallback_rules(&[], &[]),
+                   "<?xml version=\"1.0\"?>\n<!DOCTYPE fontconfig SYSTEM \"fonts.dtd\">\n<fontconfig>\n</fontconfig>\n");
+    }
+
+    #[test]
+    fn rules() {
+        let xml = fallback_rules(&["Noto Sans".to_owned(), "A & \"B\" <C>".to_owned()], &[range('\u{e000}', '\u{f8ff}', "Icons")]);
+        let general = concat!(
+            "  <match target=\"pattern\">\n",
+            "    <test name=\"charset\" compare=\"not_eq\"><minus><name>charset</name>",
+            "<charset><range><int>0</int><int>1114111</int></range></charset></minus></test>\n",
+            "    <edit name=\"family\" mode=\"prepend_first\" binding=\"strong\">",
+            "<string>Noto Sans</string><string>A &amp; &quot;B&quot; &lt;C&gt;</string></edit>\n",
+            "  </match>\n");
+        let icons = concat!(
+            "  <match target=\"pattern\">\n",
+            "    <test name=\"charset\" compare=\"not_eq\"><minus><name>charset</name>",
+            "<charset><range><int>57344</int><int>63743</int></range></charset></minus></test>\n",
+            "    <edit name=\"family\" mode=\"prepend_first\" binding=\"strong\"><string>Icons</string></edit>\n",
+            "  </match>\n");
+        assert!(xml.starts_with("<?xml version=\"1.0\"?>\n<!DOCTYPE fontconfig SYSTEM \"fonts.dtd\">\n<fontconfig>\n"));
+        assert!(xml.ends_with(&format!("{}{}</fontconfig>\n", general, icons)));
+    }
+}
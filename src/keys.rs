@@ -0,0 +1,410 @@
+use gdk;
+use gdk::ModifierType as Mod;
+use gdk::enums::key;
+
+use alacritty::term::TermMode;
+
+/// How the application wants special keys to be sent
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Mode {
+    /// DECCKM: cursor keys send `SS3 A` instead of `CSI A`
+    pub app_cursor: bool,
+    /// DECKPAM: the keypad sends `SS3` sequences instead of digits
+    pub app_keypad: bool,
+    /// xterm's modifyOtherKeys, set with `CSI > 4 ; n m`: 0 is off, 1 encodes
+    /// only the chords that have no usual meaning, 2 encodes all of them
+    pub modify_other_keys: u8,
+}
+
+impl Mode {
+    pub fn from_term(mode: &TermMode, modify_other_keys: u8) -> Mode {
+        Mode {
+            app_cursor: mode.contains(TermMode::APP_CURSOR),
+            app_keypad: mode.contains(TermMode::APP_KEYPAD),
+            modify_other_keys,
+        }
+    }
+}
+
+/// What holding Alt does to the characters of a key
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AltMode {
+    /// Sends ESC before them, what readline and Emacs expect
+    Escape,
+    /// Sets the eighth bit, for the rare application that wants it (xterm's metaSendsEscape off)
+    EightBit,
+}
+
+/// What the Backspace key sends
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BackspaceMode {
+    /// `^?`
+    AsciiDelete,
+    /// `^H`
+    AsciiBackspace,
+}
+
+/// What the Delete key sends
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DeleteMode {
+    /// `CSI 3 ~`
+    EscapeSequence,
+    /// `^?`
+    AsciiDelete,
+}
+
+impl AltMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AltMode::Escape => "escape",
+            AltMode::EightBit => "eight-bit",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<AltMode> {
+        match s {
+            "escape" => Some(AltMode::Escape),
+            "eight-bit" => Some(AltMode::EightBit),
+            _ => None,
+        }
+    }
+}
+
+impl BackspaceMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BackspaceMode::AsciiDelete => "ascii-delete",
+            BackspaceMode::AsciiBackspace => "ascii-backspace",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<BackspaceMode> {
+        match s {
+            "ascii-delete" => Some(BackspaceMode::AsciiDelete),
+            "ascii-backspace" => Some(BackspaceMode::AsciiBackspace),
+            _ => None,
+        }
+    }
+}
+
+impl DeleteMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DeleteMode::EscapeSequence => "escape-sequence",
+            DeleteMode::AsciiDelete => "ascii-delete",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<DeleteMode> {
+        match s {
+            "escape-sequence" => Some(DeleteMode::EscapeSequence),
+            "ascii-delete" => Some(DeleteMode::AsciiDelete),
+            _ => None,
+        }
+    }
+}
+
+/// Keyboard settings from the preferences
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Options {
+    pub alt: AltMode,
+    pub backspace: BackspaceMode,
+    pub delete: DeleteMode,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            alt: AltMode::Escape,
+            backspace: BackspaceMode::AsciiDelete,
+            delete: DeleteMode::EscapeSequence,
+        }
+    }
+}
+
+fn alt_held(mods: Mod) -> bool {
+    mods.contains(Mod::MOD1_MASK) || mods.contains(Mod::META_MASK)
+}
+
+/// The xterm modifier parameter: 1 + Shift + 2·Alt + 4·Control + 8·Meta
+///
+/// Super is what most keyboards have in place of Meta.
+pub fn modifier_param(mods: Mod) -> u8 {
+    let mut param = 1;
+    if mods.contains(Mod::SHIFT_MASK) {
+        param += 1;
+    }
+    if alt_held(mods) {
+        param += 2;
+    }
+    if mods.contains(Mod::CONTROL_MASK) {
+        param += 4;
+    }
+    if mods.contains(Mod::SUPER_MASK) {
+        param += 8;
+    }
+    param
+}
+
+/// Modifier and lock keys, which send nothing by themselves
+pub fn is_modifier(kv: u32) -> bool {
+    match kv {
+        key::Super_L | key::Super_R | key::Hyper_L | key::Hyper_R | key::Control_L | key::Control_R |
+            key::Alt_L | key::Alt_R | key::Meta_L | key::Meta_R | key::Shift_L | key::Shift_R |
+            key::Caps_Lock | key::Scroll_Lock | key::Shift_Lock | key::ModeLock |
+            key::Num_Lock | key::ISO_Level3_Shift | key::ISO_Level5_Shift => true,
+        _ => false,
+    }
+}
+
+/// `CSI A` / `SS3 A` style keys: the cursor keys, Home, End and F1-F4
+fn letter_key(kv: u32) -> Option<(char, bool)> {
+    match kv {
+        // the bool says whether the key follows the application cursor mode
+        key::Up | key::KP_Up => Some(('A', true)),
+        key::Down | key::KP_Down => Some(('B', true)),
+        key::Right | key::KP_Right => Some(('C', true)),
+        key::Left | key::KP_Left => Some(('D', true)),
+        key::KP_Begin => Some(('E', true)),
+        key::End | key::KP_End => Some(('F', true)),
+        key::Home | key::KP_Home => Some(('H', true)),
+        key::F1 | key::KP_F1 => Some(('P', false)),
+        key::F2 | key::KP_F2 => Some(('Q', false)),
+        key::F3 | key::KP_F3 => Some(('R', false)),
+        key::F4 | key::KP_F4 => Some(('S', false)),
+        _ => None,
+    }
+}
+
+/// `CSI n ~` style keys
+fn tilde_key(kv: u32) -> Option<u8> {
+    match kv {
+        key::Insert | key::KP_Insert => Some(2),
+        key::Page_Up | key::KP_Page_Up => Some(5),
+        key::Page_Down | key::KP_Page_Down => Some(6),
+        key::F5 => Some(15),
+        key::F6 => Some(17),
+        key::F7 => Some(18),
+        key::F8 => Some(19),
+        key::F9 => Some(20),
+        key::F10 => Some(21),
+        key::F11 => Some(23),
+        key::F12 => Some(24),
+        _ => None,
+    }
+}
+
+/// The final character of keypad keys in application keypad mode
+fn keypad_key(kv: u32) -> Option<char> {
+    match kv {
+        key::KP_0..=key::KP_9 => Some((b'p' + (kv - key::KP_0) as u8) as char),
+        key::KP_Decimal => Some('n'),
+        key::KP_Separator => Some('l'),
+        key::KP_Add => Some('k'),
+        key::KP_Subtract => Some('m'),
+        key::KP_Multiply => Some('j'),
+        key::KP_Divide => Some('o'),
+        key::KP_Equal => Some('X'),
+        key::KP_Enter => Some('M'),
+        _ => None,
+    }
+}
+
+fn is_latin(kv: u32) -> bool {
+    gdk::keyval_to_unicode(kv).map(|c| c.is_ascii()).unwrap_or(false)
+}
+
+/// Finds the key in a Latin layout for Control and Alt chords, like VTE does.
+///
+/// With e.g. a Russian layout active, the key with C on it produces a Cyrillic
+/// letter, but Control+C should still be ^C.
+pub fn latin_keyval(kv: u32, keycode: u16, mods: Mod) -> u32 {
+    if !mods.intersects(Mod::CONTROL_MASK | Mod::MOD1_MASK | Mod::META_MASK) {
+        return kv;
+    }
+    // special keys have no character, they're the same in every layout
+    if gdk::keyval_to_unicode(kv).map(|c| c.is_ascii()).unwrap_or(true) {
+        return kv;
+    }
+    let keymap = match gdk::Keymap::get_default() {
+        Some(keymap) => keymap,
+        None => return kv,
+    };
+    // XKB has up to four groups
+    (0..4)
+        .filter_map(|group| keymap.translate_keyboard_state(keycode.into(), mods, group))
+        .map(|(latin, _, _, _)| latin)
+        .find(|&latin| is_latin(latin))
+        .unwrap_or(kv)
+}
+
+/// Applies Alt to the bytes of a key that has no modifier parameter
+fn with_alt(mut bytes: Vec<u8>, mods: Mod, options: &Options) -> Vec<u8> {
+    if !alt_held(mods) {
+        return bytes;
+    }
+    match options.alt {
+        AltMode::EightBit if bytes.len() == 1 && bytes[0] < 0x80 => {
+            // in a UTF-8 terminal the eighth bit turns into a Latin-1 character
+            (char::from(bytes[0] | 0x80)).to_string().into_bytes()
+        },
+        _ => {
+            bytes.insert(0, 0x1b);
+            bytes
+        },
+    }
+}
+
+/// Encodes a key that doesn't just produce text, the way xterm does.
+///
+/// Returns None for keys that should be sent as the characters they produce.
+pub fn encode(kv: u32, mods: Mod, mode: Mode, options: &Options) -> Option<Vec<u8>> {
+    let param = modifier_param(mods);
+    if let Some((c, follows_mode)) = letter_key(kv) {
+        return Some(if param > 1 {
+            format!("\x1b[1;{}{}", param, c)
+        } else if follows_mode && !mode.app_cursor {
+            format!("\x1b[{}", c)
+        } else {
+            format!("\x1bO{}", c)
+        }.into_bytes());
+    }
+    if let Some(n) = tilde_key(kv) {
+        return Some(if param > 1 {
+            format!("\x1b[{};{}~", n, param)
+        } else {
+            format!("\x1b[{}~", n)
+        }.into_bytes());
+    }
+    if mode.app_keypad {
+        if let Some(c) = keypad_key(kv) {
+            return Some(if param > 1 {
+                format!("\x1bO{}{}", param, c)
+            } else {
+                format!("\x1bO{}", c)
+            }.into_bytes());
+        }
+    }
+    match kv {
+        key::ISO_Left_Tab => Some(b"\x1b[Z".to_vec()),
+        key::Tab if mods.contains(Mod::SHIFT_MASK) => Some(b"\x1b[Z".to_vec()),
+        key::BackSpace => {
+            // Control sends the other one, like in xterm
+            let ascii_delete = (options.backspace == BackspaceMode::AsciiDelete) != mods.contains(Mod::CONTROL_MASK);
+            Some(with_alt(vec![if ascii_delete { 0x7f } else { 0x08 }], mods, options))
+        },
+        key::Delete | key::KP_Delete => Some(match options.delete {
+            DeleteMode::EscapeSequence if param > 1 => format!("\x1b[3;{}~", param).into_bytes(),
+            DeleteMode::EscapeSequence => b"\x1b[3~".to_vec(),
+            DeleteMode::AsciiDelete => with_alt(vec![0x7f], mods, options),
+        }),
+        _ => None,
+    }
+}
+
+/// The control character that Control turns a character into, like in xterm
+fn control_char(c: char) -> Option<u8> {
+    match c {
+        ' ' | '2' => Some(0),
+        '3' => Some(0x1b),
+        '4' => Some(0x1c),
+        '5' => Some(0x1d),
+        '6' => Some(0x1e),
+        '7' | '/' => Some(0x1f),
+        '8' | '?' => Some(0x7f),
+        '@'..='~' => Some(c as u8 & !0x60),
+        _ => None,
+    }
+}
+
+/// Whether a Control chord has a usual meaning as a control character
+fn has_control_char(c: char, mods: Mod) -> bool {
+    !mods.contains(Mod::SHIFT_MASK) && control_char(c).is_some()
+}
+
+/// Encodes a chord as `CSI 27 ; modifiers ; character ~` for modifyOtherKeys
+fn modify_other(c: char, mods: Mod, level: u8) -> Option<Vec<u8>> {
+    let param = modifier_param(mods);
+    // Shift alone just types the shifted character, but changes nothing about Enter or Escape
+    let shift_only = param == 2;
+    if level == 0 || param == 1 || (shift_only && (level == 1 || !c.is_control())) {
+        return None;
+    }
+    if level == 1 {
+        // Alt is known to send ESC, and Control the control characters
+        let ctrl = mods.contains(Mod::CONTROL_MASK);
+        if !ctrl || has_control_char(c, mods) {
+            return None;
+        }
+    }
+    Some(format!("\x1b[27;{};{}~", param, c as u32).into_bytes())
+}
+
+/// Encodes the character that a key produced, with Control and Alt applied
+pub fn encode_char(c: char, mods: Mod, mode: Mode, options: &Options) -> Vec<u8> {
+    if let Some(bytes) = modify_other(c, mods, mode.modify_other_keys) {
+        return bytes;
+    }
+    let bytes = match control_char(c) {
+        Some(ctrl) if mods.contains(Mod::CONTROL_MASK) => vec![ctrl],
+        _ => c.to_string().into_bytes(),
+    };
+    with_alt(bytes, mods, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NORMAL: Mode = Mode { app_cursor: false, app_keypad: false, modify_other_keys: 0 };
+
+    #[test]
+    fn modified_cursor_keys() {
+        let mods = Mod::CONTROL_MASK | Mod::SHIFT_MASK;
+        assert_eq!(encode(key::Left, mods, NORMAL, &Options::default()), Some(b"\x1b[1;6D".to_vec()));
+    }
+
+    #[test]
+    fn application_cursor_keys() {
+        let mode = Mode { app_cursor: true, ..NORMAL };
+        assert_eq!(encode(key::Up, Mod::empty(), mode, &Options::default()), Some(b"\x1bOA".to_vec()));
+        assert_eq!(encode(key::Up, Mod::empty(), NORMAL, &Options::default()), Some(b"\x1b[A".to_vec()));
+    }
+
+    #[test]
+    fn control_characters() {
+        let ctrl = |c| encode_char(c, Mod::CONTROL_MASK, NORMAL, &Options::default());
+        assert_eq!(ctrl('c'), vec![0x03]);
+        assert_eq!(ctrl(' '), vec![0x00]);
+        assert_eq!(ctrl('?'), vec![0x7f]);
+        assert_eq!(ctrl('2'), vec![0x00]);
+        assert_eq!(ctrl('3'), vec![0x1b]);
+        assert_eq!(ctrl('4'), vec![0x1c]);
+        assert_eq!(ctrl('5'), vec![0x1d]);
+        assert_eq!(ctrl('6'), vec![0x1e]);
+        assert_eq!(ctrl('7'), vec![0x1f]);
+        assert_eq!(ctrl('8'), vec![0x7f]);
+        assert_eq!(ctrl('1'), b"1".to_vec());
+    }
+
+    #[test]
+    fn alt_sends_escape() {
+        let options = Options::default();
+        assert_eq!(encode_char('x', Mod::MOD1_MASK, NORMAL, &options), b"\x1bx".to_vec());
+        let options = Options { alt: AltMode::EightBit, ..options };
+        assert_eq!(encode_char('x', Mod::MOD1_MASK, NORMAL, &options), "\u{f8}".as_bytes().to_vec());
+    }
+
+    #[test]
+    fn modify_other_keys() {
+        let level1 = Mode { modify_other_keys: 1, ..NORMAL };
+        let level2 = Mode { modify_other_keys: 2, ..NORMAL };
+        let options = Options::default();
+        assert_eq!(encode_char('1', Mod::CONTROL_MASK, level1, &options), b"\x1b[27;5;49~".to_vec());
+        // the usual control characters are left alone at level 1
+        assert_eq!(encode_char('c', Mod::CONTROL_MASK, level1, &options), vec![0x03]);
+        assert_eq!(encode_char('c', Mod::CONTROL_MASK, level2, &options), b"\x1b[27;5;99~".to_vec());
+        assert_eq!(encode_char('A', Mod::SHIFT_MASK, level2, &options), b"A".to_vec());
+        assert_eq!(encode_char('\r', Mod::SHIFT_MASK, level2, &options), b"\x1b[27;2;13~".to_vec());
+    }
+}
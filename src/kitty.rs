@@ -0,0 +1,392 @@
+use gdk;
+use gdk::ModifierType as Mod;
+use gdk::enums::key;
+
+use crate::keys;
+
+/// Flags that applications push with `CSI > flags u` to enhance the keyboard input
+pub const DISAMBIGUATE: u8 = 1;
+pub const EVENT_TYPES: u8 = 2;
+pub const ALTERNATE_KEYS: u8 = 4;
+pub const ALL_KEYS: u8 = 8;
+pub const ASSOCIATED_TEXT: u8 = 16;
+const ALL_FLAGS: u8 = 31;
+
+/// How many entries the flag stack keeps before it starts dropping the oldest ones
+const STACK_SIZE: usize = 16;
+
+const SHIFT: u32 = 1;
+const ALT: u32 = 2;
+const CONTROL: u32 = 4;
+const SUPER: u32 = 8;
+const HYPER: u32 = 16;
+const CAPS_LOCK: u32 = 64;
+const NUM_LOCK: u32 = 128;
+
+/// The stack of flags that applications push, pop and change.
+///
+/// Each screen has its own, so that a full screen application doesn't have to
+/// restore what the shell had.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct FlagStack(Vec<u8>);
+
+impl FlagStack {
+    /// The flags in effect, 0 for legacy input
+    pub fn current(&self) -> u8 {
+        self.0.last().cloned().unwrap_or(0)
+    }
+
+    /// `CSI > flags u`
+    pub fn push(&mut self, flags: u8) {
+        if self.0.len() == STACK_SIZE {
+            self.0.remove(0);
+        }
+        self.0.push(flags & ALL_FLAGS);
+    }
+
+    /// `CSI < n u`, popping more entries than there are empties the stack
+    pub fn pop(&mut self, n: usize) {
+        let len = self.0.len();
+        self.0.truncate(len.saturating_sub(n));
+    }
+
+    /// `CSI = flags ; mode u`: 1 replaces the current flags, 2 sets the given bits, 3 clears them
+    pub fn set(&mut self, flags: u8, mode: u32) {
+        let current = self.current();
+        let flags = match mode {
+            2 => current | flags,
+            3 => current & !flags,
+            _ => flags,
+        } & ALL_FLAGS;
+        match self.0.last_mut() {
+            Some(top) => *top = flags,
+            None => self.0.push(flags),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    Press,
+    Repeat,
+    Release,
+}
+
+/// A key event as the protocol sees it
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Key {
+    /// What the key produces with the modifiers that are held
+    pub keyval: u32,
+    /// What it produces without them, in the current layout
+    pub unshifted: u32,
+    /// The same key in a Latin layout
+    pub base: u32,
+    pub mods: Mod,
+    pub action: Action,
+}
+
+impl Key {
+    pub fn from_event(event: &gdk::EventKey, action: Action) -> Key {
+        let keyval = event.get_keyval();
+        let keycode = event.get_hardware_keycode();
+        let unshifted = gdk::Keymap::get_default()
+            .and_then(|keymap| keymap.translate_keyboard_state(keycode.into(), Mod::empty(), event.get_group().into()))
+            .map(|(kv, _, _, _)| kv)
+            .unwrap_or(keyval);
+        Key {
+            keyval,
+            unshifted,
+            base: keys::latin_keyval(unshifted, keycode, Mod::CONTROL_MASK),
+            mods: event.get_state(),
+            action,
+        }
+    }
+}
+
+/// The modifier bits as of after the event, so pressing Shift reports Shift
+fn mod_bits(key: &Key, all_keys: bool) -> u32 {
+    let mut bits = 0;
+    if key.mods.contains(Mod::SHIFT_MASK) {
+        bits |= SHIFT;
+    }
+    if key.mods.contains(Mod::MOD1_MASK) || key.mods.contains(Mod::META_MASK) {
+        bits |= ALT;
+    }
+    if key.mods.contains(Mod::CONTROL_MASK) {
+        bits |= CONTROL;
+    }
+    if key.mods.contains(Mod::SUPER_MASK) {
+        bits |= SUPER;
+    }
+    if key.mods.contains(Mod::HYPER_MASK) {
+        bits |= HYPER;
+    }
+    // the locks only matter to applications that get every key
+    if all_keys {
+        if key.mods.contains(Mod::LOCK_MASK) {
+            bits |= CAPS_LOCK;
+        }
+        if key.mods.contains(Mod::MOD2_MASK) {
+            bits |= NUM_LOCK;
+        }
+    }
+    let own = match key.keyval {
+        key::Shift_L | key::Shift_R => SHIFT,
+        key::Alt_L | key::Alt_R | key::Meta_L | key::Meta_R => ALT,
+        key::Control_L | key::Control_R => CONTROL,
+        key::Super_L | key::Super_R => SUPER,
+        key::Hyper_L | key::Hyper_R => HYPER,
+        _ => 0,
+    };
+    if key.action == Action::Release {
+        bits & !own
+    } else {
+        bits | own
+    }
+}
+
+/// Keys that don't produce text, with their number and final character
+fn functional(kv: u32) -> Option<(u32, char)> {
+    Some(match kv {
+        key::Escape => (27, 'u'),
+        key::Return => (13, 'u'),
+        key::Tab | key::ISO_Left_Tab => (9, 'u'),
+        key::BackSpace => (127, 'u'),
+        key::Insert => (2, '~'),
+        key::Delete => (3, '~'),
+        key::Left => (1, 'D'),
+        key::Right => (1, 'C'),
+        key::Up => (1, 'A'),
+        key::Down => (1, 'B'),
+        key::Page_Up => (5, '~'),
+        key::Page_Down => (6, '~'),
+        key::Home => (1, 'H'),
+        key::End => (1, 'F'),
+        key::F1 => (1, 'P'),
+        key::F2 => (1, 'Q'),
+        key::F3 => (13, '~'),
+        key::F4 => (1, 'S'),
+        key::F5 => (15, '~'),
+        key::F6 => (17, '~'),
+        key::F7 => (18, '~'),
+        key::F8 => (19, '~'),
+        key::F9 => (20, '~'),
+        key::F10 => (21, '~'),
+        key::F11 => (23, '~'),
+        key::F12 => (24, '~'),
+        key::F13..=key::F35 => (57376 + kv - key::F13, 'u'),
+        key::Caps_Lock => (57358, 'u'),
+        key::Scroll_Lock => (57359, 'u'),
+        key::Num_Lock => (57360, 'u'),
+        key::Print => (57361, 'u'),
+        key::Pause => (57362, 'u'),
+        key::Menu => (57363, 'u'),
+        key::KP_0..=key::KP_9 => (57399 + kv - key::KP_0, 'u'),
+        key::KP_Decimal => (57409, 'u'),
+        key::KP_Divide => (57410, 'u'),
+        key::KP_Multiply => (57411, 'u'),
+        key::KP_Subtract => (57412, 'u'),
+        key::KP_Add => (57413, 'u'),
+        key::KP_Enter => (57414, 'u'),
+        key::KP_Equal => (57415, 'u'),
+        key::KP_Separator => (57416, 'u'),
+        key::KP_Left => (57417, 'u'),
+        key::KP_Right => (57418, 'u'),
+        key::KP_Up => (57419, 'u'),
+        key::KP_Down => (57420, 'u'),
+        key::KP_Page_Up => (57421, 'u'),
+        key::KP_Page_Down => (57422, 'u'),
+        key::KP_Home => (57423, 'u'),
+        key::KP_End => (57424, 'u'),
+        key::KP_Insert => (57425, 'u'),
+        key::KP_Delete => (57426, 'u'),
+        key::KP_Begin => (57427, 'u'),
+        key::Shift_L => (57441, 'u'),
+        key::Control_L => (57442, 'u'),
+        key::Alt_L => (57443, 'u'),
+        key::Super_L => (57444, 'u'),
+        key::Hyper_L => (57445, 'u'),
+        key::Meta_L => (57446, 'u'),
+        key::Shift_R => (57447, 'u'),
+        key::Control_R => (57448, 'u'),
+        key::Alt_R => (57449, 'u'),
+        key::Super_R => (57450, 'u'),
+        key::Hyper_R => (57451, 'u'),
+        key::Meta_R => (57452, 'u'),
+        key::ISO_Level3_Shift => (57453, 'u'),
+        key::ISO_Level5_Shift => (57454, 'u'),
+        _ => return None,
+    })
+}
+
+/// Modifiers and lock keys, which are only reported to applications that want every key
+fn is_modifier(number: u32) -> bool {
+    (57358..=57360).contains(&number) || (57441..=57454).contains(&number)
+}
+
+fn lowercase(c: char) -> u32 {
+    c.to_lowercase().next().unwrap_or(c) as u32
+}
+
+/// Encodes a key event as `CSI number ; modifiers u` and friends.
+///
+/// Returns None when the key should be sent the legacy way, and an empty
+/// sequence when it shouldn't be sent at all (e.g. unwanted releases).
+pub fn encode(key: &Key, flags: u8) -> Option<Vec<u8>> {
+    let all_keys = flags & ALL_KEYS != 0;
+    let action = match key.action {
+        Action::Release if flags & EVENT_TYPES == 0 => return Some(Vec::new()),
+        Action::Repeat if flags & EVENT_TYPES == 0 => Action::Press,
+        action => action,
+    };
+    let mods = mod_bits(key, all_keys);
+    let text = if mods & (ALT | CONTROL | SUPER | HYPER) == 0 {
+        gdk::keyval_to_unicode(key.keyval).filter(|c| !c.is_control())
+    } else {
+        None
+    };
+    let (number, final_char, is_text) = match functional(key.keyval) {
+        Some((number, final_char)) => (number, final_char, false),
+        None => match gdk::keyval_to_unicode(key.unshifted).or_else(|| gdk::keyval_to_unicode(key.keyval)) {
+            Some(c) => (lowercase(c), 'u', true),
+            None => return None,
+        },
+    };
+
+    if !all_keys {
+        let pressed = action != Action::Release;
+        if is_modifier(number) {
+            return Some(Vec::new());
+        }
+        // without disambiguation, only the events that legacy input can't send are encoded
+        if flags & DISAMBIGUATE == 0 && pressed {
+            return None;
+        }
+        // keys that type text, and Enter, Tab and Backspace, stay as they were
+        if is_text && text.is_some() {
+            return if pressed { None } else { Some(Vec::new()) };
+        }
+        if (number == 13 || number == 9 || number == 127) && mods == 0 {
+            return if pressed { None } else { Some(Vec::new()) };
+        }
+        // so do unmodified cursor and function keys, which follow the cursor key mode
+        if final_char != 'u' && mods == 0 && action == Action::Press {
+            return None;
+        }
+    }
+
+    let mut seq = String::from("\x1b[");
+    let mut key_field = number.to_string();
+    if flags & ALTERNATE_KEYS != 0 && final_char == 'u' && is_text {
+        let shifted = Some(key.keyval).filter(|_| mods & SHIFT != 0)
+            .and_then(gdk::keyval_to_unicode)
+            .map(|c| c as u32)
+            .filter(|&c| c != number);
+        let base = gdk::keyval_to_unicode(key.base).map(lowercase).filter(|&c| c != number);
+        match (shifted, base) {
+            (Some(shifted), Some(base)) => key_field += &format!(":{}:{}", shifted, base),
+            (Some(shifted), None) => key_field += &format!(":{}", shifted),
+            (None, Some(base)) => key_field += &format!("::{}", base),
+            (None, None) => (),
+        }
+    }
+    let text_field = if all_keys && flags & ASSOCIATED_TEXT != 0 && action != Action::Release {
+        text.map(|c| c as u32)
+    } else {
+        None
+    };
+    let action_field = match action {
+        Action::Press => None,
+        Action::Repeat => Some(2),
+        Action::Release => Some(3),
+    };
+    let has_params = mods != 0 || action_field.is_some() || text_field.is_some();
+    if number != 1 || final_char == 'u' || has_params {
+        seq += &key_field;
+    }
+    if has_params {
+        seq += &format!(";{}", mods + 1);
+        if let Some(action) = action_field {
+            seq += &format!(":{}", action);
+        }
+        if let Some(text) = text_field {
+            seq += &format!(";{}", text);
+        }
+    }
+    seq.push(final_char);
+    Some(seq.into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(keyval: u32, mods: Mod, action: Action) -> Key {
+        Key { keyval, unshifted: keyval, base: keyval, mods, action }
+    }
+
+    #[test]
+    fn disambiguates_control_chords() {
+        let ctrl_i = key(key::i, Mod::CONTROL_MASK, Action::Press);
+        assert_eq!(encode(&ctrl_i, DISAMBIGUATE), Some(b"\x1b[105;5u".to_vec()));
+        // that's Tab in legacy input
+        assert_eq!(encode(&ctrl_i, 0), None);
+    }
+
+    #[test]
+    fn text_stays_legacy() {
+        assert_eq!(encode(&key(key::a, Mod::empty(), Action::Press), DISAMBIGUATE), None);
+        assert_eq!(encode(&key(key::Return, Mod::empty(), Action::Press), DISAMBIGUATE), None);
+        assert_eq!(encode(&key(key::Escape, Mod::empty(), Action::Press), DISAMBIGUATE), Some(b"\x1b[27u".to_vec()));
+    }
+
+    #[test]
+    fn reports_event_types() {
+        let flags = DISAMBIGUATE | EVENT_TYPES;
+        assert_eq!(encode(&key(key::Left, Mod::empty(), Action::Release), flags), Some(b"\x1b[1;1:3D".to_vec()));
+        assert_eq!(encode(&key(key::Left, Mod::empty(), Action::Repeat), flags), Some(b"\x1b[1;1:2D".to_vec()));
+        assert_eq!(encode(&key(key::Left, Mod::empty(), Action::Press), flags), None);
+        // releases of keys that type text aren't wanted without ALL_KEYS
+        assert_eq!(encode(&key(key::a, Mod::empty(), Action::Release), flags), Some(Vec::new()));
+        assert_eq!(encode(&key(key::Left, Mod::empty(), Action::Release), DISAMBIGUATE), Some(Vec::new()));
+    }
+
+    #[test]
+    fn reports_all_keys() {
+        let flags = DISAMBIGUATE | EVENT_TYPES | ALL_KEYS;
+        assert_eq!(encode(&key(key::a, Mod::empty(), Action::Press), flags), Some(b"\x1b[97u".to_vec()));
+        assert_eq!(encode(&key(key::a, Mod::empty(), Action::Release), flags), Some(b"\x1b[97;1:3u".to_vec()));
+        // pressing a modifier already counts it as held
+        assert_eq!(encode(&key(key::Shift_L, Mod::empty(), Action::Press), flags), Some(b"\x1b[57441;2u".to_vec()));
+    }
+
+    #[test]
+    fn flag_stack() {
+        let mut stack = FlagStack::default();
+        assert_eq!(stack.current(), 0);
+        stack.push(DISAMBIGUATE);
+        stack.push(DISAMBIGUATE | EVENT_TYPES);
+        assert_eq!(stack.current(), DISAMBIGUATE | EVENT_TYPES);
+        stack.set(EVENT_TYPES, 3);
+        assert_eq!(stack.current(), DISAMBIGUATE);
+        stack.set(ALL_KEYS, 2);
+        assert_eq!(stack.current(), DISAMBIGUATE | ALL_KEYS);
+        stack.set(EVENT_TYPES, 1);
+        assert_eq!(stack.current(), EVENT_TYPES);
+        stack.pop(1);
+        assert_eq!(stack.current(), DISAMBIGUATE);
+        stack.pop(5);
+        assert_eq!(stack.current(), 0);
+        for _ in 0..STACK_SIZE + 1 {
+            stack.push(DISAMBIGUATE);
+        }
+        stack.pop(STACK_SIZE - 1);
+        assert_eq!(stack.current(), DISAMBIGUATE);
+        stack.pop(1);
+        assert_eq!(stack.current(), 0);
+    }
+}
@@ -6,28 +6,41 @@ use alacritty;
 #[macro_use]
 extern crate log;
 
-use std::env::args;
 use std::rc::Rc;
-use std::cell::RefCell;
+use std::cell::Cell;
+use std::env::args;
+use std::ffi::CString;
 
 use gio::prelude::*;
 use gio::{Menu, MenuExt, MenuItem, SimpleAction};
 use gtk::prelude::*;
+use glib::ToVariant;
+use glib::translate::ToGlibPtr;
 
 #[macro_use]
 pub mod util; // order matters for macros
+pub mod appearance;
+pub mod cli;
+pub mod colors;
+pub mod config;
 pub mod font;
+pub mod keys;
+pub mod kitty;
+pub mod modes;
+pub mod mouse;
+pub mod object;
+pub mod panes;
+pub mod paste;
+pub mod prefs;
+pub mod profiles;
+pub mod tabs;
 pub mod widget;
 
-fn build_actions(app: gtk::Application,
-                 window: gtk::ApplicationWindow,
-                 clipboard: gtk::Clipboard,
-                 glarea: gtk::GLArea,
-                 state: Rc<RefCell<Option<widget::State>>>) {
+fn build_app_actions(app: &gtk::Application) {
     let about_action = SimpleAction::new("HelpAbout", None);
-    about_action.connect_activate(clone!(window => move |_, _| {
+    about_action.connect_activate(clone!(app => move |_, _| {
         let about = gtk::AboutDialog::new();
-        about.set_transient_for(Some(&window));
+        about.set_transient_for(app.get_active_window().as_ref());
         about.set_program_name("Galacritty");
         about.set_version(env!("CARGO_PKG_VERSION"));
         about.set_logo_icon_name("technology.unrelenting.galacritty");
@@ -38,84 +51,213 @@ fn build_actions(app: gtk::Application,
     }));
     app.add_action(&about_action);
 
+    let confirm_paste_action = SimpleAction::new_stateful("ConfirmPaste", None, &prefs::get().confirm_paste.to_variant());
+    confirm_paste_action.connect_activate(|action, _| {
+        let confirm = !action.get_state().and_then(|s| s.get::<bool>()).unwrap_or(true);
+        action.set_state(&confirm.to_variant());
+        prefs::update(|p| p.confirm_paste = confirm);
+    });
+    app.add_action(&confirm_paste_action);
+
+    if let Some(settings) = prefs::settings() {
+        settings.connect_changed(clone!(confirm_paste_action => move |settings, key| {
+            if key == "confirm-paste" {
+                confirm_paste_action.set_state(&settings.get_boolean(key).to_variant());
+            }
+        }));
+        app.add_action(&settings.create_action("show-header-bar"));
+    }
+
+    let preferences_action = SimpleAction::new("Preferences", None);
+    preferences_action.connect_activate(clone!(app => move |_, _| {
+        prefs::show(app.get_active_window().as_ref());
+    }));
+    app.add_action(&preferences_action);
+    app.set_accels_for_action("app.Preferences", &["<Control>comma"]);
+
+    let new_window_action = SimpleAction::new("NewWindow", None);
+    new_window_action.connect_activate(clone!(app => move |_, _| {
+        build_window(&app, &cli::Options::default());
+    }));
+    app.add_action(&new_window_action);
+    app.set_accels_for_action("app.NewWindow", &["<Control><Shift>n"]);
+
+    let new_window_profile_action = SimpleAction::new("NewWindowWithProfile", glib::VariantTy::new("s").ok());
+    new_window_profile_action.connect_activate(clone!(app => move |_, param| {
+        build_window(&app, &profile_options(param));
+    }));
+    app.add_action(&new_window_profile_action);
+}
+
+/// Options for opening a terminal with the profile from an action parameter, empty for the default one
+fn profile_options(param: &Option<glib::Variant>) -> cli::Options {
+    let mut options = cli::Options::default();
+    options.profile = param.as_ref().and_then(|p| p.get::<String>()).filter(|p| !p.is_empty());
+    options
+}
+
+fn build_window_actions(app: &gtk::Application,
+                        window: gtk::ApplicationWindow,
+                        clipboard: gtk::Clipboard,
+                        tabs: tabs::Tabs) {
+    let copy_action = SimpleAction::new("Copy", None);
+    copy_action.connect_activate(clone!(tabs => move |_, _| {
+        if let Some(term) = tabs.current() {
+            term.copy_clipboard();
+        }
+    }));
+    window.add_action(&copy_action);
+    app.set_accels_for_action("win.Copy", &["<Control><Shift>c", "<Control>Insert"]);
+
     let paste_action = SimpleAction::new("Paste", None);
-    paste_action.connect_activate(clone!(glarea, state => move |_, _| {
+    paste_action.connect_activate(clone!(tabs => move |_, _| {
         if let Some(text) = clipboard.wait_for_text() {
-            let mut state = state.borrow_mut();
-            if let Some(ref mut state) = *state {
-                // TODO: bracketed paste support
-                state.event_queue.push(widget::Event::StringInput(text.replace("\r\n", "\r").replace("\n", "\r")));
+            if let Some(term) = tabs.current() {
+                term.paste_checked(text);
             }
-            glarea.queue_draw();
         }
     }));
     window.add_action(&paste_action);
     app.set_accels_for_action("win.Paste", &["<Control><Shift>v", "<Shift>Insert"]);
 
     let font_decr_action = SimpleAction::new("FontDecrease", None);
-    font_decr_action.connect_activate(clone!(glarea, state => move |_, _| {
-        let mut state = state.borrow_mut();
-        if let Some(ref mut state) = *state {
-            state.event_queue.push(widget::Event::ChangeFontSize(-1));
+    font_decr_action.connect_activate(clone!(tabs => move |_, _| {
+        if let Some(term) = tabs.current() {
+            term.change_font_size(-1);
         }
-        glarea.queue_draw();
     }));
     window.add_action(&font_decr_action);
     app.set_accels_for_action("win.FontDecrease", &["<Control>minus", "<Control>KP_Subtract"]);
 
     let font_incr_action = SimpleAction::new("FontIncrease", None);
-    font_incr_action.connect_activate(clone!(glarea, state => move |_, _| {
-        let mut state = state.borrow_mut();
-        if let Some(ref mut state) = *state {
-            state.event_queue.push(widget::Event::ChangeFontSize(1));
+    font_incr_action.connect_activate(clone!(tabs => move |_, _| {
+        if let Some(term) = tabs.current() {
+            term.change_font_size(1);
         }
-        glarea.queue_draw();
     }));
     window.add_action(&font_incr_action);
     app.set_accels_for_action("win.FontIncrease", &["<Control>equal", "<Control>plus", "<Control>KP_Add"]);
 
     let font_choose_action = SimpleAction::new("FontChoose", None);
-    font_choose_action.connect_activate(clone!(glarea, window, state => move |_, _| {
-        glarea.set_auto_render(false);
-        let mut state = state.borrow_mut();
-        if let Some(ref mut state) = *state {
-            let curf = state.config.font();
-            let dial = gtk::FontChooserDialog::new("Choose Terminal Font", Some(&window));
-            dial.set_preview_text("if c0de$[1337] { \"hello\".world(); /* test */ }");
-            dial.set_font(&format!("{} {}", curf.normal.family, curf.size.as_f32_pts()));
-            let acc : i32 = gtk::ResponseType::Ok.into();
-            if dial.run() == acc {
-                if let Some(fam) = dial.get_font_family() {
-                    let newf = font::to_alacritty(fam, dial.get_font_size());
-                    let fontdiff = (newf.size.as_f32_pts() - curf.size.as_f32_pts()) as i8;
-                    state.config.set_font(newf);
-                    state.event_queue.push(widget::Event::ChangeFontSize(fontdiff));
-                    // force reload the glyph cache if the size didn't change
-                    state.event_queue.push(widget::Event::HiDPIFactorChanged(glarea.get_scale_factor() as f32));
-                }
+    font_choose_action.connect_activate(clone!(window, tabs => move |_, _| {
+        let term = match tabs.current() {
+            Some(term) => term,
+            None => return,
+        };
+        let curf = match term.get_font() {
+            Some(curf) => curf,
+            None => return,
+        };
+        let dial = gtk::FontChooserDialog::new("Choose Terminal Font", Some(&window));
+        dial.set_preview_text("if c0de$[1337] { \"hello\".world(); /* test */ }");
+        dial.set_font(&prefs::get().terminal.font.unwrap_or_else(|| font::to_description(&curf)));
+        let acc : i32 = gtk::ResponseType::Ok.into();
+        if dial.run() == acc {
+            // saved and applied to all terminals
+            if let Some(desc) = dial.get_font() {
+                prefs::update(|p| p.terminal.font = Some(desc));
             }
-            dial.destroy();
         }
-        glarea.set_auto_render(true);
-        glarea.queue_draw();
+        dial.destroy();
     }));
     window.add_action(&font_choose_action);
 
     let font_reset_action = SimpleAction::new("FontReset", None);
-    font_reset_action.connect_activate(move |_, _| {
-        let mut state = state.borrow_mut();
-        if let Some(ref mut state) = *state {
-            state.event_queue.push(widget::Event::ResetFontSize);
+    font_reset_action.connect_activate(clone!(tabs => move |_, _| {
+        if let Some(term) = tabs.current() {
+            term.reset_font_size();
         }
-        glarea.queue_draw();
-    });
+    }));
     window.add_action(&font_reset_action);
     app.set_accels_for_action("win.FontReset", &["<Control>0", "<Control>KP_0"]);
+
+    let new_tab_action = SimpleAction::new("NewTab", None);
+    new_tab_action.connect_activate(clone!(tabs => move |_, _| {
+        tabs.open(&cli::Options::default());
+    }));
+    window.add_action(&new_tab_action);
+    app.set_accels_for_action("win.NewTab", &["<Control><Shift>t"]);
+
+    let new_tab_profile_action = SimpleAction::new("NewTabWithProfile", glib::VariantTy::new("s").ok());
+    new_tab_profile_action.connect_activate(clone!(tabs => move |_, param| {
+        tabs.open(&profile_options(param));
+    }));
+    window.add_action(&new_tab_profile_action);
+
+    let close_tab_action = SimpleAction::new("CloseTab", None);
+    close_tab_action.connect_activate(clone!(tabs => move |_, _| {
+        tabs.close_current();
+    }));
+    window.add_action(&close_tab_action);
+    app.set_accels_for_action("win.CloseTab", &["<Control><Shift>w"]);
+
+    let next_tab_action = SimpleAction::new("NextTab", None);
+    next_tab_action.connect_activate(clone!(tabs => move |_, _| {
+        tabs.switch_by(1);
+    }));
+    window.add_action(&next_tab_action);
+    app.set_accels_for_action("win.NextTab", &["<Control>Page_Down"]);
+
+    let prev_tab_action = SimpleAction::new("PrevTab", None);
+    prev_tab_action.connect_activate(clone!(tabs => move |_, _| {
+        tabs.switch_by(-1);
+    }));
+    window.add_action(&prev_tab_action);
+    app.set_accels_for_action("win.PrevTab", &["<Control>Page_Up"]);
+
+    let split_horiz_action = SimpleAction::new("SplitHorizontal", None);
+    split_horiz_action.connect_activate(clone!(tabs => move |_, _| {
+        tabs.split_current(gtk::Orientation::Horizontal);
+    }));
+    window.add_action(&split_horiz_action);
+    app.set_accels_for_action("win.SplitHorizontal", &["<Control><Shift>e"]);
+
+    let split_vert_action = SimpleAction::new("SplitVertical", None);
+    split_vert_action.connect_activate(clone!(tabs => move |_, _| {
+        tabs.split_current(gtk::Orientation::Vertical);
+    }));
+    window.add_action(&split_vert_action);
+    app.set_accels_for_action("win.SplitVertical", &["<Control><Shift>o"]);
+
+    let close_pane_action = SimpleAction::new("ClosePane", None);
+    close_pane_action.connect_activate(clone!(tabs => move |_, _| {
+        tabs.close_current_pane();
+    }));
+    window.add_action(&close_pane_action);
+    app.set_accels_for_action("win.ClosePane", &["<Control><Shift>x"]);
+
+    // with vi's direction keys, applications get modified arrows for themselves
+    for &(dir, name, key) in &[(panes::Direction::Left, "Left", "h"), (panes::Direction::Right, "Right", "l"),
+                               (panes::Direction::Up, "Up", "k"), (panes::Direction::Down, "Down", "j")] {
+        let focus_action = SimpleAction::new(&format!("FocusPane{}", name), None);
+        focus_action.connect_activate(clone!(tabs => move |_, _| {
+            tabs.focus_neighbour(dir);
+        }));
+        window.add_action(&focus_action);
+        app.set_accels_for_action(&format!("win.FocusPane{}", name), &[&*format!("<Control><Shift>{}", key)]);
+
+        let resize_action = SimpleAction::new(&format!("ResizePane{}", name), None);
+        resize_action.connect_activate(clone!(tabs => move |_, _| {
+            tabs.resize_current(dir);
+        }));
+        window.add_action(&resize_action);
+        app.set_accels_for_action(&format!("win.ResizePane{}", name), &[&*format!("<Control><Shift><Alt>{}", key)]);
+    }
 }
 
 fn build_main_menu() -> Menu {
     let menu = Menu::new();
 
+    let section = Menu::new();
+    section.append_item(&MenuItem::new("New Window", "app.NewWindow"));
+    menu.append_section(None, &section);
+
+    let section = Menu::new();
+    section.append_item(&MenuItem::new("Confirm Unsafe Paste", "app.ConfirmPaste"));
+    section.append_item(&MenuItem::new("Show Header Bar", "app.show-header-bar"));
+    section.append_item(&MenuItem::new("Preferences", "app.Preferences"));
+    menu.append_section(None, &section);
+
     let section = Menu::new();
     section.append_item(&MenuItem::new("About", "app.HelpAbout"));
     menu.append_section(None, &section);
@@ -127,6 +269,18 @@ fn build_main_menu() -> Menu {
 fn build_header_bar() -> gtk::HeaderBar {
     let header_bar = gtk::HeaderBar::new();
 
+    let new_tab_btn = gtk::Button::new_from_icon_name("tab-new-symbolic", gtk::IconSize::SmallToolbar.into());
+    new_tab_btn.set_can_focus(false);
+    new_tab_btn.set_tooltip_text("Open a new tab");
+    new_tab_btn.set_action_name("win.NewTab");
+    header_bar.pack_start(&new_tab_btn);
+
+    let profile_btn = gtk::MenuButton::new();
+    profile_btn.set_can_focus(false);
+    profile_btn.set_tooltip_text("Open a tab or window with a profile");
+    profile_btn.set_menu_model(Some(&profiles::menu()));
+    header_bar.pack_start(&profile_btn);
+
     let font_decr_btn = gtk::Button::new_from_icon_name("zoom-out-symbolic", gtk::IconSize::SmallToolbar.into());
     font_decr_btn.set_can_focus(false);
     font_decr_btn.set_tooltip_text("Decrease font size");
@@ -145,24 +299,66 @@ fn build_header_bar() -> gtk::HeaderBar {
     font_incr_btn.set_action_name("win.FontIncrease");
     header_bar.pack_start(&font_incr_btn);
 
+    let split_btn = gtk::MenuButton::new();
+    split_btn.set_can_focus(false);
+    split_btn.set_tooltip_text("Split the terminal");
+    split_btn.set_image(&gtk::Image::new_from_icon_name("view-dual-symbolic", gtk::IconSize::SmallToolbar.into()));
+    let split_menu = Menu::new();
+    split_menu.append_item(&MenuItem::new("Split Side by Side", "win.SplitHorizontal"));
+    split_menu.append_item(&MenuItem::new("Split Top and Bottom", "win.SplitVertical"));
+    split_menu.append_item(&MenuItem::new("Close Pane", "win.ClosePane"));
+    split_btn.set_menu_model(Some(&split_menu));
+    header_bar.pack_end(&split_btn);
+
     let paste_btn = gtk::Button::new_from_icon_name("edit-paste-symbolic", gtk::IconSize::SmallToolbar.into());
     paste_btn.set_can_focus(false);
     paste_btn.set_tooltip_text("Paste from clipboard");
     paste_btn.set_action_name("win.Paste");
     header_bar.pack_end(&paste_btn);
 
+    let copy_btn = gtk::Button::new_from_icon_name("edit-copy-symbolic", gtk::IconSize::SmallToolbar.into());
+    copy_btn.set_can_focus(false);
+    copy_btn.set_tooltip_text("Copy selection to clipboard");
+    copy_btn.set_action_name("win.Copy");
+    header_bar.pack_end(&copy_btn);
+
     header_bar.set_show_close_button(true);
     header_bar
 }
 
-fn build_ui(app: &gtk::Application) {
-    gtk::Window::set_default_icon_name("technology.unrelenting.galacritty");
-
+fn build_window(app: &gtk::Application, options: &cli::Options) {
     let window = gtk::ApplicationWindow::new(app);
 
-    window.set_title("Galacritty");
+    window.set_title(options.title.as_ref().map(|t| &**t).unwrap_or("Galacritty"));
+    if let Some(ref class) = options.class {
+        window.set_wmclass(class, class);
+    }
     window.set_border_width(0);
-    window.set_default_size(1280, 720);
+    match prefs::settings() {
+        Some(ref settings) => {
+            window.set_default_size(settings.get_int("window-width"), settings.get_int("window-height"));
+            if settings.get_boolean("window-maximized") {
+                window.maximize();
+            }
+        },
+        None => window.set_default_size(1280, 720),
+    }
+
+    // remembered for the next window, the size of a maximized window isn't interesting
+    let size = Rc::new(Cell::new(window.get_default_size()));
+    window.connect_size_allocate(clone!(size => move |window, _| {
+        if !window.is_maximized() {
+            size.set(window.get_size());
+        }
+    }));
+    window.connect_destroy(move |window| {
+        if let Some(settings) = prefs::settings() {
+            let (width, height) = size.get();
+            settings.set_int("window-width", width);
+            settings.set_int("window-height", height);
+            settings.set_boolean("window-maximized", window.is_maximized());
+        }
+    });
 
     window.connect_delete_event(clone!(window => move |_, _| {
         window.destroy();
@@ -174,13 +370,70 @@ fn build_ui(app: &gtk::Application) {
     let header_bar = build_header_bar();
     window.set_titlebar(Some(&header_bar));
 
-    let (glarea, state) = widget::alacritty_widget(window.clone(), header_bar);
+    let tabs = tabs::Tabs::new(window.clone(), header_bar.clone());
 
-    build_actions(app.clone(), window.clone(), clipboard, glarea.clone(), state.clone());
+    build_window_actions(app, window.clone(), clipboard, tabs.clone());
 
-    app.set_app_menu(Some(&build_main_menu()));
-    window.add(&glarea);
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 0);
+    vbox.pack_start(&config::error_bar(), false, false, 0);
+    vbox.pack_start(&tabs.notebook, true, true, 0);
+    window.add(&vbox);
     window.show_all();
+    if let Some(settings) = prefs::settings() {
+        settings.bind("show-header-bar", &header_bar, "visible", gio::SettingsBindFlags::GET);
+    }
+    tabs.open(options);
+}
+
+fn build_ui(app: &gtk::Application) {
+    gtk::Window::set_default_icon_name("technology.unrelenting.galacritty");
+
+    appearance::watch();
+    config::watch();
+    build_app_actions(app);
+    app.set_app_menu(Some(&build_main_menu()));
+}
+
+/// Prints to the stdout of the process that the command line came from
+fn print(cmdline: &gio::ApplicationCommandLine, text: &str) {
+    let text = CString::new(text).unwrap_or_default();
+    // the printf-style functions aren't in the bindings
+    unsafe { gio_sys::g_application_command_line_print(cmdline.to_glib_none().0, b"%s\n\0".as_ptr() as *const _, text.as_ptr()) }
+}
+
+/// Prints to the stderr of the process that the command line came from
+fn printerr(cmdline: &gio::ApplicationCommandLine, text: &str) {
+    let text = CString::new(text).unwrap_or_default();
+    unsafe { gio_sys::g_application_command_line_printerr(cmdline.to_glib_none().0, b"%s\n\0".as_ptr() as *const _, text.as_ptr()) }
+}
+
+/// Opens a window for a command line, which might have been forwarded from another instance
+fn handle_command_line(app: &gtk::Application, cmdline: &gio::ApplicationCommandLine) -> i32 {
+    let args = cmdline.get_arguments().into_iter().skip(1).map(|a| a.to_string_lossy().into_owned());
+    match cli::parse(args) {
+        Ok(ref options) if options.help => {
+            print(cmdline, cli::USAGE);
+            0
+        },
+        Ok(mut options) => {
+            options.resolve_working_dir(cmdline.get_cwd().as_ref().map(|d| &**d));
+            if let Some(name) = options.profile.take() {
+                match profiles::find(&name) {
+                    Some(profile) => options.profile = Some(profile.id),
+                    None => {
+                        printerr(cmdline, &format!("Unknown profile {}", name));
+                        return 1;
+                    },
+                }
+            }
+            build_window(app, &options);
+            0
+        },
+        Err(err) => {
+            printerr(cmdline, &format!("{}\n\n{}", err, cli::USAGE));
+            1
+        },
+    }
 }
 
 fn main() {
@@ -188,13 +441,16 @@ fn main() {
 
     let application = gtk::Application::new(
         "technology.unrelenting.galacritty",
-        gio::ApplicationFlags::empty()
+        gio::ApplicationFlags::HANDLES_COMMAND_LINE
     ).expect("gtk::Application::new");
 
     application.connect_startup(|app| {
         build_ui(app);
     });
-    application.connect_activate(|_| {});
+    application.connect_activate(|app| {
+        build_window(app, &cli::Options::default());
+    });
+    application.connect_command_line(handle_command_line);
 
     application.run(&args().collect::<Vec<_>>());
 }
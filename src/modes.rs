@@ -0,0 +1,480 @@
+use std::io::{self, Read, Write};
+use std::mem;
+use std::net::Shutdown;
+use std::thread;
+use std::fs::File;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::os::unix::net::UnixStream;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+
+use libc;
+
+use alacritty::event_loop::WindowNotifier;
+use alacritty::tty::Pty;
+
+use crate::kitty;
+
+/// Keyboard modes that applications set, Alacritty doesn't know about them
+#[derive(Clone, Debug, Default)]
+pub struct InputModes {
+    main_flags: kitty::FlagStack,
+    alt_flags: kitty::FlagStack,
+    alt_screen: bool,
+    /// xterm's modifyOtherKeys level, see keys::Mode
+    modify_other_keys: u8,
+}
+
+impl InputModes {
+    /// The kitty keyboard protocol flags of the current screen, 0 for legacy input
+    pub fn keyboard_flags(&self) -> u8 {
+        self.flags().current()
+    }
+
+    pub fn modify_other_keys(&self) -> u8 {
+        self.modify_other_keys
+    }
+
+    fn flags(&self) -> &kitty::FlagStack {
+        if self.alt_screen { &self.alt_flags } else { &self.main_flags }
+    }
+
+    fn flags_mut(&mut self) -> &mut kitty::FlagStack {
+        if self.alt_screen { &mut self.alt_flags } else { &mut self.main_flags }
+    }
+
+    fn reset(&mut self) {
+        *self = InputModes::default();
+    }
+}
+
+/// Longest control sequence that's looked at, anything longer isn't one of ours
+const MAX_SEQUENCE: usize = 32;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum ScanState {
+    Ground,
+    Escape,
+    EscapeIntermediate,
+    Csi,
+    /// A CSI sequence that's too long to be one of ours
+    CsiIgnore,
+    /// OSC, DCS, APC and friends, which end with ST or BEL
+    String,
+    StringEscape,
+}
+
+impl Default for ScanState {
+    fn default() -> ScanState {
+        ScanState::Ground
+    }
+}
+
+/// Finds the sequences that change the input modes in the output of the child, and takes
+/// out the ones that Alacritty doesn't know, its parser would mistake them for others
+#[derive(Default)]
+pub struct Scanner {
+    state: ScanState,
+    /// What's between `CSI` and the final byte
+    sequence: Vec<u8>,
+    /// The start of a sequence that might be taken out
+    held: Vec<u8>,
+}
+
+impl Scanner {
+    /// Looks through output of the child, adding what Alacritty should see to `output`
+    /// and returning what to reply
+    pub fn scan(&mut self, data: &[u8], modes: &mut InputModes, output: &mut Vec<u8>) -> Vec<u8> {
+        let mut reply = Vec::new();
+        for &byte in data {
+            self.advance(byte, modes, output, &mut reply);
+        }
+        reply
+    }
+
+    /// Passes on the start of a sequence that's still held back, e.g. once the child is gone
+    pub fn flush(&mut self, output: &mut Vec<u8>) {
+        output.extend(self.held.drain(..));
+    }
+
+    fn advance(&mut self, byte: u8, modes: &mut InputModes, output: &mut Vec<u8>, reply: &mut Vec<u8>) {
+        // like in Alacritty's parser, an ESC that isn't part of ST ends the string and starts a
+        // sequence, it's held already
+        if self.state == ScanState::StringEscape && byte != b'\\' {
+            self.state = ScanState::Escape;
+        }
+        self.state = match (self.state, byte) {
+            (ScanState::String, 0x1b) => {
+                self.held.push(byte);
+                ScanState::StringEscape
+            },
+            (ScanState::String, _) => {
+                output.push(byte);
+                if byte == 0x07 { ScanState::Ground } else { ScanState::String }
+            },
+            (ScanState::StringEscape, _) => self.pass(byte, output, ScanState::Ground),
+            (_, 0x1b) => {
+                self.flush(output);
+                self.held.push(byte);
+                ScanState::Escape
+            },
+            // CAN and SUB abort sequences
+            (_, 0x18) | (_, 0x1a) => self.pass(byte, output, ScanState::Ground),
+            (ScanState::Escape, b'[') => {
+                self.sequence.clear();
+                self.held.push(byte);
+                ScanState::Csi
+            },
+            (ScanState::Escape, b']') | (ScanState::Escape, b'P') | (ScanState::Escape, b'_') |
+                (ScanState::Escape, b'^') | (ScanState::Escape, b'X') => self.pass(byte, output, ScanState::String),
+            (ScanState::Escape, b'c') => {
+                // RIS
+                modes.reset();
+                self.pass(byte, output, ScanState::Ground)
+            },
+            // the other escape sequences are one character, maybe after intermediates
+            (ScanState::Escape, 0x20..=0x2f) | (ScanState::EscapeIntermediate, 0x20..=0x2f) => {
+                self.held.push(byte);
+                ScanState::EscapeIntermediate
+            },
+            (ScanState::Escape, _) | (ScanState::EscapeIntermediate, _) => self.pass(byte, output, ScanState::Ground),
+            (ScanState::Csi, 0x40..=0x7e) => {
+                if csi(&self.sequence, byte, modes, reply) {
+                    self.held.clear();
+                    ScanState::Ground
+                } else {
+                    self.pass(byte, output, ScanState::Ground)
+                }
+            },
+            (ScanState::Csi, 0x20..=0x3f) if self.sequence.len() < MAX_SEQUENCE => {
+                self.sequence.push(byte);
+                self.held.push(byte);
+                ScanState::Csi
+            },
+            (ScanState::Csi, 0x20..=0x3f) | (ScanState::CsiIgnore, 0x20..=0x3f) => self.pass(byte, output, ScanState::CsiIgnore),
+            // C0 controls are executed in the middle of sequences
+            (ScanState::Csi, 0x00..=0x1f) | (ScanState::CsiIgnore, 0x00..=0x1f) => {
+                output.push(byte);
+                self.state
+            },
+            (ScanState::Csi, _) | (ScanState::CsiIgnore, _) | (ScanState::Ground, _) => self.pass(byte, output, ScanState::Ground),
+        };
+    }
+
+    /// Passes on what's held with the byte after it
+    fn pass(&mut self, byte: u8, output: &mut Vec<u8>, next: ScanState) -> ScanState {
+        self.flush(output);
+        output.push(byte);
+        next
+    }
+}
+
+/// The numeric parameters of a sequence, without sub-parameters, None where they're empty
+fn params(sequence: &[u8]) -> Vec<Option<u32>> {
+    sequence.split(|&b| b == b';')
+        .map(|param| param.split(|&b| b == b':').next().unwrap_or(&[]))
+        .map(|param| String::from_utf8_lossy(param).parse::<u32>().ok())
+        .collect()
+}
+
+/// Handles a CSI sequence, returning whether it's one that Alacritty shouldn't see
+fn csi(sequence: &[u8], final_byte: u8, modes: &mut InputModes, reply: &mut Vec<u8>) -> bool {
+    if sequence.iter().any(|&b| b < 0x30) {
+        // with intermediates
+        return false;
+    }
+    let (marker, rest) = match sequence.first() {
+        Some(&m) if m >= 0x3c => (Some(m), &sequence[1..]),
+        _ => (None, sequence),
+    };
+    let params = params(rest);
+    let param = |i: usize, default: u32| params.get(i).cloned().and_then(|p| p).unwrap_or(default);
+    match (marker, final_byte) {
+        // Alacritty would restore the cursor for all of these
+        (Some(b'>'), b'u') => modes.flags_mut().push(param(0, 0) as u8),
+        (Some(b'<'), b'u') => modes.flags_mut().pop(param(0, 1) as usize),
+        (Some(b'='), b'u') => modes.flags_mut().set(param(0, 0) as u8, param(1, 1)),
+        (Some(b'?'), b'u') => reply.extend(format!("\x1b[?{}u", modes.keyboard_flags()).into_bytes()),
+        // xterm's key modifier resources, Alacritty would take `CSI > … m` for SGR
+        // `CSI > 4 ; n m` sets the level, leaving it out resets it, like `CSI > m` does to all of them
+        (Some(b'>'), b'm') => if param(0, 4) == 4 {
+            modes.modify_other_keys = param(1, 0).min(2) as u8;
+        },
+        // `CSI > 4 n` turns it off
+        (Some(b'>'), b'n') => if param(0, 4) == 4 {
+            modes.modify_other_keys = 0;
+        },
+        (Some(b'?'), b'h') | (Some(b'?'), b'l') => {
+            // the alternate screen, with or without saving the cursor and clearing it
+            if params.iter().any(|&p| p == Some(1049) || p == Some(1047) || p == Some(47)) {
+                modes.alt_screen = final_byte == b'h';
+            }
+            return false;
+        },
+        _ => return false,
+    }
+    true
+}
+
+fn set_nonblocking(fd: &impl AsRawFd) -> io::Result<()> {
+    let fd = fd.as_raw_fd();
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        if flags < 0 || libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+fn is_retry(err: &io::Error) -> bool {
+    err.kind() == io::ErrorKind::WouldBlock || err.kind() == io::ErrorKind::Interrupted
+}
+
+/// The pty master, shared with the relay so it stays open as long as either uses it
+pub struct Master {
+    file: Arc<File>,
+    hung_up: Arc<AtomicBool>,
+}
+
+impl Master {
+    /// The fd to resize the pty with
+    pub fn fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+
+    /// Whether the child's side of the pty is closed and everything it wrote was relayed
+    pub fn hung_up(&self) -> bool {
+        self.hung_up.load(Ordering::SeqCst)
+    }
+}
+
+/// The child's process ID, it's the leader of the pty's session
+fn session_leader(fd: &impl AsRawFd) -> io::Result<libc::pid_t> {
+    let mut sid: libc::pid_t = 0;
+    if unsafe { libc::ioctl(fd.as_raw_fd(), libc::TIOCGSID, &mut sid) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(sid)
+}
+
+/// Puts a socket between the pty and Alacritty's event loop, to see what the child
+/// sends before Alacritty parses it.
+///
+/// The notifier is called when the child hangs up. The master is closed, hanging up
+/// on the child, once the event loop is done and the Master is dropped. The relay
+/// reaps the child after that, SIGCHLD has to be left to the default.
+pub fn relay<N>(pty: &mut Pty, modes: Arc<Mutex<InputModes>>, notifier: N) -> io::Result<Master>
+    where N: WindowNotifier + Send + 'static
+{
+    let (ours, theirs) = UnixStream::pair()?;
+    // the event loop expects what the pty is like
+    theirs.set_nonblocking(true)?;
+    set_nonblocking(&pty.fd)?;
+    let pid = session_leader(&pty.fd)?;
+    let file = mem::replace(&mut pty.fd, unsafe { File::from_raw_fd(theirs.into_raw_fd()) });
+    let master = Master { file: Arc::new(file), hung_up: Arc::new(AtomicBool::new(false)) };
+    let (file, hung_up) = (Arc::clone(&master.file), Arc::clone(&master.hung_up));
+    thread::Builder::new()
+        .name("pty relay".to_owned())
+        .spawn(move || {
+            if run_relay(&file, ours, &modes) {
+                hung_up.store(true, Ordering::SeqCst);
+                notifier.notify();
+            }
+            // the child might wait for the master to be closed
+            drop(file);
+            let mut status = 0;
+            while unsafe { libc::waitpid(pid, &mut status, 0) } < 0 && is_retry(&io::Error::last_os_error()) {}
+        })?;
+    Ok(master)
+}
+
+/// Relays until either side is done, returning whether it was the child
+fn run_relay(mut master: &File, mut socket: UnixStream, modes: &Mutex<InputModes>) -> bool {
+    let mut scanner = Scanner::default();
+    let mut buf = [0; 4096];
+    let mut output = Vec::new();
+    // input and replies that the pty didn't take yet
+    let mut pending = Vec::new();
+    let mut child_gone = false;
+    loop {
+        let mut fds = [
+            libc::pollfd {
+                fd: master.as_raw_fd(),
+                events: libc::POLLIN | if pending.is_empty() { 0 } else { libc::POLLOUT },
+                revents: 0,
+            },
+            libc::pollfd { fd: socket.as_raw_fd(), events: libc::POLLIN, revents: 0 },
+        ];
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } < 0 {
+            if is_retry(&io::Error::last_os_error()) {
+                continue;
+            }
+            break;
+        }
+        if fds[0].revents & (libc::POLLIN | libc::POLLHUP | libc::POLLERR) != 0 {
+            match master.read(&mut buf) {
+                Ok(n) if n > 0 => {
+                    let reply = scanner.scan(&buf[..n], &mut modes.lock().expect("input modes"), &mut output);
+                    pending.extend(reply);
+                    if socket.write_all(&output).is_err() {
+                        break;
+                    }
+                    output.clear();
+                },
+                Err(ref err) if is_retry(err) => (),
+                // EIO once the child is gone and everything it wrote was read
+                _ => {
+                    scanner.flush(&mut output);
+                    let _ = socket.write_all(&output);
+                    child_gone = true;
+                    break;
+                },
+            }
+        }
+        if fds[1].revents & (libc::POLLIN | libc::POLLHUP | libc::POLLERR) != 0 {
+            match socket.read(&mut buf) {
+                Ok(n) if n > 0 => pending.extend_from_slice(&buf[..n]),
+                Err(ref err) if is_retry(err) => (),
+                // the event loop is done
+                _ => break,
+            }
+        }
+        if !pending.is_empty() {
+            match master.write(&pending) {
+                Ok(n) => { pending.drain(..n); },
+                Err(ref err) if is_retry(err) => (),
+                Err(_) => pending.clear(),
+            }
+        }
+    }
+    let _ = socket.shutdown(Shutdown::Write);
+    child_gone
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scan(modes: &mut InputModes, data: &[u8]) -> Vec<u8> {
+        Scanner::default().scan(data, modes, &mut Vec::new())
+    }
+
+    /// What Alacritty gets to see
+    fn output(modes: &mut InputModes, data: &[&[u8]]) -> Vec<u8> {
+        let mut scanner = Scanner::default();
+        let mut output = Vec::new();
+        for data in data {
+            scanner.scan(data, modes, &mut output);
+        }
+        scanner.flush(&mut output);
+        output
+    }
+
+    #[test]
+    fn kitty_flag_stack() {
+        let mut modes = InputModes::default();
+        scan(&mut modes, b"\x1b[>1u");
+        assert_eq!(modes.keyboard_flags(), 1);
+        scan(&mut modes, b"\x1b[>3u");
+        assert_eq!(modes.keyboard_flags(), 3);
+        scan(&mut modes, b"\x1b[=8;2u");
+        assert_eq!(modes.keyboard_flags(), 11);
+        scan(&mut modes, b"\x1b[<u");
+        assert_eq!(modes.keyboard_flags(), 1);
+        scan(&mut modes, b"\x1b[<10u");
+        assert_eq!(modes.keyboard_flags(), 0);
+    }
+
+    #[test]
+    fn kitty_query() {
+        let mut modes = InputModes::default();
+        assert_eq!(scan(&mut modes, b"\x1b[?u"), b"\x1b[?0u".to_vec());
+        assert_eq!(scan(&mut modes, b"text\x1b[>5u\x1b[?u\x1b[c"), b"\x1b[?5u".to_vec());
+    }
+
+    #[test]
+    fn screens_have_their_own_flags() {
+        let mut modes = InputModes::default();
+        scan(&mut modes, b"\x1b[>1u\x1b[?1049h");
+        assert_eq!(modes.keyboard_flags(), 0);
+        scan(&mut modes, b"\x1b[>31u");
+        assert_eq!(modes.keyboard_flags(), 31);
+        scan(&mut modes, b"\x1b[?1049l");
+        assert_eq!(modes.keyboard_flags(), 1);
+        scan(&mut modes, b"\x1bc");
+        assert_eq!(modes.keyboard_flags(), 0);
+    }
+
+    #[test]
+    fn modify_other_keys() {
+        let mut modes = InputModes::default();
+        scan(&mut modes, b"\x1b[>4;1m");
+        assert_eq!(modes.modify_other_keys(), 1);
+        scan(&mut modes, b"\x1b[>4;2m");
+        assert_eq!(modes.modify_other_keys(), 2);
+        // other resources
+        scan(&mut modes, b"\x1b[>1;2m\x1b[>2n");
+        assert_eq!(modes.modify_other_keys(), 2);
+        scan(&mut modes, b"\x1b[>4m");
+        assert_eq!(modes.modify_other_keys(), 0);
+        scan(&mut modes, b"\x1b[>4;1m\x1b[>4n");
+        assert_eq!(modes.modify_other_keys(), 0);
+        scan(&mut modes, b"\x1b[>4;2m\x1b[>m");
+        assert_eq!(modes.modify_other_keys(), 0);
+        // SGR without the marker
+        scan(&mut modes, b"\x1b[4;2m");
+        assert_eq!(modes.modify_other_keys(), 0);
+    }
+
+    #[test]
+    fn takes_out_modifier_resources() {
+        let mut modes = InputModes::default();
+        assert_eq!(output(&mut modes, &[b"a\x1b[>4;2mb\x1b[>1mc\x1b[>4nd"]), b"abcd".to_vec());
+        assert_eq!(output(&mut modes, &[b"\x1b[4;2m\x1b[?4m"]), b"\x1b[4;2m\x1b[?4m".to_vec());
+    }
+
+    #[test]
+    fn sequences_split_between_reads() {
+        let mut modes = InputModes::default();
+        let mut scanner = Scanner::default();
+        scanner.scan(b"\x1b[", &mut modes, &mut Vec::new());
+        scanner.scan(b">1", &mut modes, &mut Vec::new());
+        scanner.scan(b"u", &mut modes, &mut Vec::new());
+        assert_eq!(modes.keyboard_flags(), 1);
+    }
+
+    #[test]
+    fn takes_out_kitty_sequences() {
+        let mut modes = InputModes::default();
+        assert_eq!(output(&mut modes, &[b"a\x1b[>1ub\x1b[?uc\x1b[<u\x1b[=1;2ud"]), b"abcd".to_vec());
+        assert_eq!(output(&mut modes, &[b"a\x1b", b"[>", b"1u", b"b"]), b"ab".to_vec());
+        // C0 controls in the middle are executed anyway
+        assert_eq!(output(&mut modes, &[b"\x1b[>\r1u"]), b"\r".to_vec());
+    }
+
+    #[test]
+    fn passes_other_sequences_on() {
+        let mut modes = InputModes::default();
+        let data: &[&[u8]] = &[
+            b"\x1b[u\x1b[?1049h\x1b[1;31mx\x1b(0\x1b]2;t\x1b\\\x1b]2;t\x07",
+            b"\x1b[>1\x18\x1b[",
+        ];
+        assert_eq!(output(&mut modes, data), data.concat());
+        let long = format!("\x1b[>{}u", "1;".repeat(MAX_SEQUENCE));
+        assert_eq!(output(&mut modes, &[long.as_bytes()]), long.into_bytes());
+        // a string that ends with another sequence
+        assert_eq!(output(&mut modes, &[b"\x1b]2;t\x1b[>1ux"]), b"\x1b]2;tx".to_vec());
+    }
+
+    #[test]
+    fn ignores_other_sequences() {
+        let mut modes = InputModes::default();
+        // restoring the cursor, a DCS that happens to look like a push, and a charset designation
+        scan(&mut modes, b"\x1b[u\x1bP>1u\x1b\\\x1b[1 u\x1b(c");
+        assert_eq!(modes.keyboard_flags(), 0);
+        scan(&mut modes, b"\x1b]2;title\x1b[>1u");
+        assert_eq!(modes.keyboard_flags(), 1);
+    }
+}
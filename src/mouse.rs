@@ -0,0 +1,290 @@
+use gdk::ModifierType as Mod;
+
+use alacritty::term::TermMode;
+
+/// Which pointer events the application wants to receive
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Tracking {
+    Off,
+    /// Mode 9: presses only, no modifiers
+    X10,
+    /// Mode 1000: presses and releases
+    Normal,
+    /// Mode 1002: also motion while a button is held
+    ButtonEvent,
+    /// Mode 1003: all motion
+    AnyEvent,
+}
+
+/// How the coordinates and button are written into the report
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Encoding {
+    /// Single bytes offset by 32, limited to 223 cells
+    Default,
+    /// Mode 1005: same as default but coordinates are UTF-8 encoded
+    Utf8,
+    /// Mode 1006: `CSI < b ; x ; y M/m`
+    Sgr,
+    /// Mode 1015: `CSI b ; x ; y M`
+    Urxvt,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Mode {
+    pub tracking: Tracking,
+    pub encoding: Encoding,
+}
+
+impl Mode {
+    pub fn from_term(mode: &TermMode) -> Mode {
+        let tracking = if mode.contains(TermMode::MOUSE_MOTION) {
+            Tracking::AnyEvent
+        } else if mode.contains(TermMode::MOUSE_DRAG) {
+            Tracking::ButtonEvent
+        } else if mode.contains(TermMode::MOUSE_REPORT_CLICK) {
+            Tracking::Normal
+        } else if mode.contains(TermMode::MOUSE_X10) {
+            Tracking::X10
+        } else {
+            Tracking::Off
+        };
+        let encoding = if mode.contains(TermMode::SGR_MOUSE) {
+            Encoding::Sgr
+        } else if mode.contains(TermMode::URXVT_MOUSE) {
+            Encoding::Urxvt
+        } else if mode.contains(TermMode::UTF8_MOUSE) {
+            Encoding::Utf8
+        } else {
+            Encoding::Default
+        };
+        Mode { tracking, encoding }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.tracking != Tracking::Off
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Button {
+    Left,
+    Middle,
+    Right,
+    WheelUp,
+    WheelDown,
+    WheelLeft,
+    WheelRight,
+    Back,
+    Forward,
+    /// Motion without any buttons held
+    None,
+}
+
+impl Button {
+    /// Converts a GDK button number
+    pub fn from_gdk(button: u32) -> Option<Button> {
+        match button {
+            1 => Some(Button::Left),
+            2 => Some(Button::Middle),
+            3 => Some(Button::Right),
+            8 => Some(Button::Back),
+            9 => Some(Button::Forward),
+            _ => None,
+        }
+    }
+
+    /// Finds the held button for motion events, from the modifier state
+    pub fn from_state(mods: Mod) -> Button {
+        if mods.contains(Mod::BUTTON1_MASK) {
+            Button::Left
+        } else if mods.contains(Mod::BUTTON2_MASK) {
+            Button::Middle
+        } else if mods.contains(Mod::BUTTON3_MASK) {
+            Button::Right
+        } else {
+            Button::None
+        }
+    }
+
+    fn code(self) -> u8 {
+        match self {
+            Button::Left => 0,
+            Button::Middle => 1,
+            Button::Right => 2,
+            Button::None => 3,
+            Button::WheelUp => 64,
+            Button::WheelDown => 65,
+            Button::WheelLeft => 66,
+            Button::WheelRight => 67,
+            Button::Back => 128,
+            Button::Forward => 129,
+        }
+    }
+
+    fn is_wheel(self) -> bool {
+        match self {
+            Button::WheelUp | Button::WheelDown | Button::WheelLeft | Button::WheelRight => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    Press,
+    Release,
+    Motion,
+}
+
+/// Encodes a pointer event at the given zero-based cell, if the mode wants to hear about it
+pub fn report(mode: Mode, button: Button, action: Action, mods: Mod, col: usize, line: usize) -> Option<Vec<u8>> {
+    match (mode.tracking, action) {
+        (Tracking::Off, _) => return None,
+        (Tracking::X10, Action::Press) if !button.is_wheel() => (),
+        (Tracking::X10, _) => return None,
+        (_, Action::Release) if button.is_wheel() => return None,
+        (Tracking::Normal, Action::Motion) => return None,
+        (Tracking::ButtonEvent, Action::Motion) if button == Button::None => return None,
+        _ => (),
+    }
+
+    let mut code = button.code();
+    if mode.tracking != Tracking::X10 {
+        if mods.contains(Mod::SHIFT_MASK) {
+            code += 4;
+        }
+        if mods.contains(Mod::MOD1_MASK) || mods.contains(Mod::META_MASK) {
+            code += 8;
+        }
+        if mods.contains(Mod::CONTROL_MASK) {
+            code += 16;
+        }
+    }
+    if action == Action::Motion {
+        code += 32;
+    }
+
+    let (x, y) = (col + 1, line + 1);
+    match mode.encoding {
+        Encoding::Sgr => {
+            let suffix = if action == Action::Release { 'm' } else { 'M' };
+            Some(format!("\x1b[<{};{};{}{}", code, x, y, suffix).into_bytes())
+        },
+        Encoding::Urxvt => {
+            let code = if action == Action::Release { 3 + (code & !3) } else { code };
+            Some(format!("\x1b[{};{};{}M", 32 + code as usize, x, y).into_bytes())
+        },
+        Encoding::Default | Encoding::Utf8 => {
+            // these can't tell which button was released
+            let code = if action == Action::Release { 3 + (code & !3) } else { code };
+            let mut msg = b"\x1b[M".to_vec();
+            msg.push(32 + code);
+            push_coord(&mut msg, x, mode.encoding)?;
+            push_coord(&mut msg, y, mode.encoding)?;
+            Some(msg)
+        },
+    }
+}
+
+fn push_coord(msg: &mut Vec<u8>, pos: usize, encoding: Encoding) -> Option<()> {
+    let val = 32 + pos;
+    if encoding == Encoding::Utf8 && val > 127 {
+        // 2015 is the largest position that fits into a two byte sequence
+        if val > 0x7ff {
+            return None;
+        }
+        msg.push(0xc0 | (val >> 6) as u8);
+        msg.push(0x80 | (val & 0x3f) as u8);
+    } else {
+        if val > 255 {
+            return None;
+        }
+        msg.push(val as u8);
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mode(tracking: Tracking, encoding: Encoding) -> Mode {
+        Mode { tracking, encoding }
+    }
+
+    const NORMAL: Mode = Mode { tracking: Tracking::Normal, encoding: Encoding::Default };
+    const SGR: Mode = Mode { tracking: Tracking::Normal, encoding: Encoding::Sgr };
+
+    #[test]
+    fn default_encoding() {
+        assert_eq!(report(NORMAL, Button::Left, Action::Press, Mod::empty(), 0, 0), Some(b"\x1b[M !!".to_vec()));
+        // releases don't say which button
+        assert_eq!(report(NORMAL, Button::Right, Action::Release, Mod::empty(), 9, 4), Some(b"\x1b[M#*%".to_vec()));
+        assert_eq!(report(NORMAL, Button::Left, Action::Press, Mod::empty(), 222, 0), Some(b"\x1b[M \xff!".to_vec()));
+        assert_eq!(report(NORMAL, Button::Left, Action::Press, Mod::empty(), 223, 0), None);
+        assert_eq!(report(NORMAL, Button::Left, Action::Press, Mod::empty(), 0, 223), None);
+    }
+
+    #[test]
+    fn utf8_encoding() {
+        let utf8 = mode(Tracking::Normal, Encoding::Utf8);
+        assert_eq!(report(utf8, Button::Left, Action::Press, Mod::empty(), 9, 4), Some(b"\x1b[M *%".to_vec()));
+        assert_eq!(report(utf8, Button::Left, Action::Press, Mod::empty(), 199, 0), Some(b"\x1b[M \xc3\xa8!".to_vec()));
+        assert_eq!(report(utf8, Button::Left, Action::Press, Mod::empty(), 2014, 0), Some(b"\x1b[M \xdf\xbf!".to_vec()));
+        assert_eq!(report(utf8, Button::Left, Action::Press, Mod::empty(), 2015, 0), None);
+    }
+
+    #[test]
+    fn sgr_encoding() {
+        assert_eq!(report(SGR, Button::Left, Action::Press, Mod::empty(), 9, 4), Some(b"\x1b[<0;10;5M".to_vec()));
+        assert_eq!(report(SGR, Button::Right, Action::Release, Mod::empty(), 9, 4), Some(b"\x1b[<2;10;5m".to_vec()));
+        // no limit on the coordinates
+        assert_eq!(report(SGR, Button::Left, Action::Press, Mod::empty(), 2999, 0), Some(b"\x1b[<0;3000;1M".to_vec()));
+    }
+
+    #[test]
+    fn urxvt_encoding() {
+        let urxvt = mode(Tracking::Normal, Encoding::Urxvt);
+        assert_eq!(report(urxvt, Button::Middle, Action::Press, Mod::empty(), 0, 0), Some(b"\x1b[33;1;1M".to_vec()));
+        assert_eq!(report(urxvt, Button::Middle, Action::Release, Mod::empty(), 299, 0), Some(b"\x1b[35;300;1M".to_vec()));
+    }
+
+    #[test]
+    fn x10_reports_only_presses() {
+        let x10 = mode(Tracking::X10, Encoding::Default);
+        let mods = Mod::SHIFT_MASK | Mod::CONTROL_MASK;
+        assert_eq!(report(x10, Button::Left, Action::Press, mods, 0, 0), Some(b"\x1b[M !!".to_vec()));
+        assert_eq!(report(x10, Button::Left, Action::Release, mods, 0, 0), None);
+        assert_eq!(report(x10, Button::Left, Action::Motion, mods, 0, 0), None);
+        assert_eq!(report(x10, Button::WheelUp, Action::Press, mods, 0, 0), None);
+    }
+
+    #[test]
+    fn wheel() {
+        assert_eq!(report(NORMAL, Button::WheelUp, Action::Press, Mod::empty(), 0, 0), Some(b"\x1b[M`!!".to_vec()));
+        assert_eq!(report(SGR, Button::WheelDown, Action::Press, Mod::empty(), 0, 0), Some(b"\x1b[<65;1;1M".to_vec()));
+        assert_eq!(report(SGR, Button::WheelLeft, Action::Press, Mod::empty(), 0, 0), Some(b"\x1b[<66;1;1M".to_vec()));
+        assert_eq!(report(SGR, Button::WheelRight, Action::Press, Mod::empty(), 0, 0), Some(b"\x1b[<67;1;1M".to_vec()));
+        assert_eq!(report(SGR, Button::WheelUp, Action::Release, Mod::empty(), 0, 0), None);
+    }
+
+    #[test]
+    fn modifiers() {
+        let press = |mods| report(SGR, Button::Left, Action::Press, mods, 0, 0);
+        assert_eq!(press(Mod::SHIFT_MASK), Some(b"\x1b[<4;1;1M".to_vec()));
+        assert_eq!(press(Mod::MOD1_MASK), Some(b"\x1b[<8;1;1M".to_vec()));
+        assert_eq!(press(Mod::META_MASK), Some(b"\x1b[<8;1;1M".to_vec()));
+        assert_eq!(press(Mod::CONTROL_MASK), Some(b"\x1b[<16;1;1M".to_vec()));
+        assert_eq!(press(Mod::SHIFT_MASK | Mod::MOD1_MASK | Mod::CONTROL_MASK), Some(b"\x1b[<28;1;1M".to_vec()));
+    }
+
+    #[test]
+    fn motion() {
+        let button_event = mode(Tracking::ButtonEvent, Encoding::Sgr);
+        let any_event = mode(Tracking::AnyEvent, Encoding::Sgr);
+        assert_eq!(report(SGR, Button::Left, Action::Motion, Mod::empty(), 0, 0), None);
+        assert_eq!(report(button_event, Button::Left, Action::Motion, Mod::empty(), 0, 0), Some(b"\x1b[<32;1;1M".to_vec()));
+        assert_eq!(report(button_event, Button::None, Action::Motion, Mod::empty(), 0, 0), None);
+        assert_eq!(report(any_event, Button::None, Action::Motion, Mod::empty(), 0, 0), Some(b"\x1b[<35;1;1M".to_vec()));
+    }
+}
@@ -0,0 +1,206 @@
+//! GalacrittyTerminal, the terminal widget as a GObject type
+//!
+//! gtk-rs can't subclass widgets yet, so the type is registered by hand:
+//! a GtkBin that owns a Terminal and holds its widgets, with the Terminal's
+//! signals and settings as GObject signals and properties. It's only for use
+//! inside of galacritty, there's no library for other programs to link to.
+
+use std::{mem, ptr};
+use std::os::raw::c_uint;
+use std::sync::Once;
+
+use glib;
+use glib::translate::*;
+use glib_sys::{self, gpointer, GType};
+use gobject_sys::{self, GObject, GParamSpec, GValue};
+use gtk;
+use gtk::prelude::*;
+use gtk_sys;
+
+use crate::font;
+use crate::widget::Terminal;
+
+#[repr(C)]
+pub struct GalacrittyTerminal {
+    parent: gtk_sys::GtkBin,
+    /// Owned by the instance until it's destroyed
+    terminal: *mut Terminal,
+}
+
+#[repr(C)]
+pub struct GalacrittyTerminalClass {
+    parent_class: gtk_sys::GtkBinClass,
+}
+
+const PROP_WINDOW_TITLE: c_uint = 1;
+const PROP_HOLD: c_uint = 2;
+const PROP_PROFILE: c_uint = 3;
+const PROP_FONT: c_uint = 4;
+
+/// The Terminal's signals, none of them have arguments
+const SIGNALS: &[&[u8]] = &[b"child-exited\0", b"window-title-changed\0", b"bell\0", b"contents-changed\0"];
+
+static mut PARENT_CLASS: *mut gtk_sys::GtkWidgetClass = ptr::null_mut();
+
+/// Registers the type the first time
+pub fn get_type() -> GType {
+    static REGISTER: Once = Once::new();
+    static mut TYPE: GType = 0;
+    unsafe {
+        REGISTER.call_once(|| {
+            TYPE = gobject_sys::g_type_register_static_simple(
+                gtk_sys::gtk_bin_get_type(),
+                b"GalacrittyTerminal\0".as_ptr() as *const _,
+                mem::size_of::<GalacrittyTerminalClass>() as c_uint,
+                Some(class_init),
+                mem::size_of::<GalacrittyTerminal>() as c_uint,
+                Some(instance_init),
+                0,
+            );
+        });
+        TYPE
+    }
+}
+
+/// Creates a GalacrittyTerminal and returns its Terminal
+pub fn new() -> Terminal {
+    unsafe {
+        let instance = gobject_sys::g_object_new(get_type(), ptr::null()) as *mut GalacrittyTerminal;
+        let terminal = (*(*instance).terminal).clone();
+        // the Terminal's reference is the only one, like with the gtk-rs constructors
+        gobject_sys::g_object_ref_sink(instance as *mut _);
+        gobject_sys::g_object_unref(instance as *mut _);
+        terminal
+    }
+}
+
+unsafe extern "C" fn class_init(klass: gpointer, _data: gpointer) {
+    PARENT_CLASS = gobject_sys::g_type_class_peek_parent(klass) as *mut _;
+    let object_class = klass as *mut gobject_sys::GObjectClass;
+    (*object_class).set_property = Some(set_property);
+    (*object_class).get_property = Some(get_property);
+    (*(klass as *mut gtk_sys::GtkWidgetClass)).destroy = Some(destroy);
+
+    let readable = gobject_sys::G_PARAM_READABLE | gobject_sys::G_PARAM_STATIC_STRINGS;
+    let readwrite = gobject_sys::G_PARAM_READWRITE | gobject_sys::G_PARAM_STATIC_STRINGS;
+    gobject_sys::g_object_class_install_property(object_class, PROP_WINDOW_TITLE, gobject_sys::g_param_spec_string(
+        b"window-title\0".as_ptr() as *const _,
+        b"Window title\0".as_ptr() as *const _,
+        b"The title last set by the application\0".as_ptr() as *const _,
+        ptr::null(),
+        readable,
+    ));
+    gobject_sys::g_object_class_install_property(object_class, PROP_HOLD, gobject_sys::g_param_spec_boolean(
+        b"hold\0".as_ptr() as *const _,
+        b"Hold\0".as_ptr() as *const _,
+        b"Keep showing the terminal after the child exits\0".as_ptr() as *const _,
+        glib_sys::GFALSE,
+        readwrite,
+    ));
+    gobject_sys::g_object_class_install_property(object_class, PROP_PROFILE, gobject_sys::g_param_spec_string(
+        b"profile\0".as_ptr() as *const _,
+        b"Profile\0".as_ptr() as *const _,
+        b"ID of the profile the configuration comes from, NULL for the default one\0".as_ptr() as *const _,
+        ptr::null(),
+        readwrite,
+    ));
+    gobject_sys::g_object_class_install_property(object_class, PROP_FONT, gobject_sys::g_param_spec_string(
+        b"font\0".as_ptr() as *const _,
+        b"Font\0".as_ptr() as *const _,
+        b"The font as a Pango font description\0".as_ptr() as *const _,
+        ptr::null(),
+        readwrite,
+    ));
+
+    let itype = (*(klass as *mut gobject_sys::GTypeClass)).g_type;
+    for name in SIGNALS {
+        gobject_sys::g_signal_newv(
+            name.as_ptr() as *const _,
+            itype,
+            gobject_sys::G_SIGNAL_RUN_LAST,
+            ptr::null_mut(),
+            None,
+            ptr::null_mut(),
+            None,
+            glib::Type::Unit.to_glib(),
+            0,
+            ptr::null_mut(),
+        );
+    }
+}
+
+unsafe extern "C" fn instance_init(instance: *mut gobject_sys::GTypeInstance, _klass: gpointer) {
+    // not a reference, the instance owns the Terminal and not the other way around
+    let container: gtk::Bin = from_glib_borrow(instance as *mut gtk_sys::GtkBin);
+    let terminal = Terminal::create(container);
+    terminal.connect_child_exited(|term| emit(term, "child-exited"));
+    terminal.connect_window_title_changed(|term| {
+        term.widget().notify("window-title");
+        emit(term, "window-title-changed");
+    });
+    terminal.connect_bell(|term| emit(term, "bell"));
+    terminal.connect_contents_changed(|term| emit(term, "contents-changed"));
+    (*(instance as *mut GalacrittyTerminal)).terminal = Box::into_raw(Box::new(terminal));
+}
+
+fn emit(term: &Terminal, signal: &str) {
+    if let Err(err) = term.widget().emit(signal, &[]) {
+        warn!("Could not emit {}: {}", signal, err);
+    }
+}
+
+/// Drops the Terminal, also when the last reference goes away without destroying the widget
+unsafe extern "C" fn destroy(widget: *mut gtk_sys::GtkWidget) {
+    // the Terminal's handlers go away with its widgets, before the Terminal itself
+    if let Some(parent_destroy) = (*PARENT_CLASS).destroy {
+        parent_destroy(widget);
+    }
+    let this = widget as *mut GalacrittyTerminal;
+    if !(*this).terminal.is_null() {
+        drop(Box::from_raw((*this).terminal));
+        (*this).terminal = ptr::null_mut();
+    }
+}
+
+/// The instance's Terminal, None once it's destroyed
+unsafe fn terminal<'a>(object: *mut GObject) -> Option<&'a Terminal> {
+    (*(object as *mut GalacrittyTerminal)).terminal.as_ref()
+}
+
+unsafe extern "C" fn set_property(object: *mut GObject, id: c_uint, value: *const GValue, _pspec: *mut GParamSpec) {
+    let terminal = match terminal(object) {
+        Some(terminal) => terminal,
+        None => return,
+    };
+    match id {
+        PROP_HOLD => terminal.set_hold(from_glib(gobject_sys::g_value_get_boolean(value))),
+        PROP_PROFILE => {
+            let profile: Option<String> = from_glib_none(gobject_sys::g_value_get_string(value));
+            terminal.set_profile(profile.as_ref().map(|p| &**p));
+        },
+        PROP_FONT => {
+            let desc: Option<String> = from_glib_none(gobject_sys::g_value_get_string(value));
+            if let Some(desc) = desc {
+                terminal.set_font(font::from_description(&desc));
+            }
+        },
+        _ => warn!("GalacrittyTerminal has no writable property {}", id),
+    }
+}
+
+unsafe extern "C" fn get_property(object: *mut GObject, id: c_uint, value: *mut GValue, _pspec: *mut GParamSpec) {
+    let terminal = match terminal(object) {
+        Some(terminal) => terminal,
+        None => return,
+    };
+    match id {
+        PROP_WINDOW_TITLE => gobject_sys::g_value_set_string(value, terminal.get_window_title().to_glib_none().0),
+        PROP_HOLD => gobject_sys::g_value_set_boolean(value, terminal.get_hold().to_glib()),
+        PROP_PROFILE => gobject_sys::g_value_set_string(value, terminal.get_profile().to_glib_none().0),
+        PROP_FONT => {
+            let desc = terminal.get_font().map(|font| font::to_description(&font));
+            gobject_sys::g_value_set_string(value, desc.to_glib_none().0);
+        },
+        _ => warn!("GalacrittyTerminal has no property {}", id),
+    }
+}
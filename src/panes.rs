@@ -0,0 +1,129 @@
+use gtk;
+use gtk::prelude::*;
+
+/// Directions for moving focus between panes and resizing them
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Direction {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+impl Direction {
+    fn orientation(self) -> gtk::Orientation {
+        match self {
+            Direction::Left | Direction::Right => gtk::Orientation::Horizontal,
+            Direction::Up | Direction::Down => gtk::Orientation::Vertical,
+        }
+    }
+}
+
+fn is_same<A: IsA<gtk::Widget>, B: IsA<gtk::Widget>>(a: &A, b: &B) -> bool {
+    a.upcast_ref::<gtk::Widget>() == b.upcast_ref::<gtk::Widget>()
+}
+
+/// Puts `new` into the slot of `child` inside of its parent,
+/// which is either a Paned or the Box of a tab page
+fn replace_child(parent: &gtk::Widget, child: &gtk::Widget, new: &gtk::Widget) {
+    if let Some(paned) = parent.downcast_ref::<gtk::Paned>() {
+        let first = paned.get_child1().map(|c| c == *child).unwrap_or(false);
+        paned.remove(child);
+        if first {
+            paned.pack1(new, true, false);
+        } else {
+            paned.pack2(new, true, false);
+        }
+    } else if let Some(container) = parent.downcast_ref::<gtk::Box>() {
+        container.remove(child);
+        container.pack_start(new, true, true, 0);
+    }
+}
+
+/// Splits the space taken by `pane` in half, putting `new` after it
+pub fn split<A: IsA<gtk::Widget>, B: IsA<gtk::Widget>>(pane: &A, new: &B, orientation: gtk::Orientation) {
+    let pane = pane.upcast_ref::<gtk::Widget>();
+    let parent = match pane.get_parent() {
+        Some(parent) => parent,
+        None => return,
+    };
+    let size = match orientation {
+        gtk::Orientation::Horizontal => pane.get_allocated_width(),
+        _ => pane.get_allocated_height(),
+    };
+
+    let paned = gtk::Paned::new(orientation);
+    paned.set_wide_handle(false);
+    replace_child(&parent, pane, paned.upcast_ref());
+    paned.pack1(pane, true, false);
+    paned.pack2(new, true, false);
+    paned.set_position(size / 2);
+    paned.show_all();
+}
+
+/// Removes `pane` from the tree, giving its space to the other side of the split.
+///
+/// Returns false if it wasn't in a split.
+pub fn collapse<A: IsA<gtk::Widget>>(pane: &A) -> bool {
+    let pane = pane.upcast_ref::<gtk::Widget>();
+    let paned = match pane.get_parent().and_then(|p| p.downcast::<gtk::Paned>().ok()) {
+        Some(paned) => paned,
+        None => return false,
+    };
+    let other = if paned.get_child1().map(|c| c == *pane).unwrap_or(false) {
+        paned.get_child2()
+    } else {
+        paned.get_child1()
+    };
+    paned.remove(pane);
+    if let (Some(other), Some(grandparent)) = (other, paned.get_parent()) {
+        paned.remove(&other);
+        replace_child(&grandparent, paned.upcast_ref(), &other);
+    }
+    paned.destroy();
+    true
+}
+
+/// Finds the pane next to `from` in the given direction, out of `panes`, all inside of `root`
+pub fn neighbour<R, W>(root: &R, from: &W, panes: &[W], dir: Direction) -> Option<W>
+    where R: IsA<gtk::Widget>, W: IsA<gtk::Widget> + Clone {
+    let rect = |w: &W| {
+        w.translate_coordinates(root, 0, 0).map(|(x, y)| {
+            (x, y, x + w.get_allocated_width(), y + w.get_allocated_height())
+        })
+    };
+    let (fx1, fy1, fx2, fy2) = rect(from)?;
+    panes.iter()
+        .filter(|w| !is_same(*w, from))
+        .filter_map(|w| rect(w).map(|r| (w, r)))
+        .filter_map(|(w, (x1, y1, x2, y2))| {
+            // the handle between the panes is a few pixels wide
+            let (gap, overlap) = match dir {
+                Direction::Left => (fx1 - x2, fy1 < y2 && y1 < fy2),
+                Direction::Right => (x1 - fx2, fy1 < y2 && y1 < fy2),
+                Direction::Up => (fy1 - y2, fx1 < x2 && x1 < fx2),
+                Direction::Down => (y1 - fy2, fx1 < x2 && x1 < fx2),
+            };
+            if overlap && gap >= 0 { Some((gap, w)) } else { None }
+        })
+        .min_by_key(|&(gap, _)| gap)
+        .map(|(_, w)| w.clone())
+}
+
+/// Moves the closest split handle around `pane` in the given direction
+pub fn resize<A: IsA<gtk::Widget>>(pane: &A, dir: Direction, step: i32) {
+    let mut parent = pane.upcast_ref::<gtk::Widget>().get_parent();
+    while let Some(widget) = parent {
+        if let Some(paned) = widget.downcast_ref::<gtk::Paned>() {
+            if paned.get_orientation() == dir.orientation() {
+                let delta = match dir {
+                    Direction::Left | Direction::Up => -step,
+                    Direction::Right | Direction::Down => step,
+                };
+                paned.set_position(paned.get_position() + delta);
+                return;
+            }
+        }
+        parent = widget.get_parent();
+    }
+}
@@ -0,0 +1,160 @@
+use gtk;
+use gtk::prelude::*;
+
+use crate::prefs;
+
+const RESPONSE_ONE_LINE: i32 = 1;
+
+/// Prepares text for sending to the pty as a paste.
+///
+/// In bracketed paste mode (2004) the text is wrapped in start/end markers,
+/// with any markers inside of it removed so that it can't end the paste early.
+pub fn to_bytes(text: &str, bracketed: bool) -> Vec<u8> {
+    let mut text = text.replace("\r\n", "\r").replace("\n", "\r");
+    if !bracketed {
+        return text.into_bytes();
+    }
+    // removing one marker could join the pieces of another one around it
+    while text.contains("\x1b[200~") || text.contains("\x1b[201~") {
+        text = text.replace("\x1b[200~", "").replace("\x1b[201~", "");
+    }
+    let mut bytes = b"\x1b[200~".to_vec();
+    bytes.extend_from_slice(text.as_bytes());
+    bytes.extend_from_slice(b"\x1b[201~");
+    bytes
+}
+
+/// Lists the reasons why pasting this text without bracketing might do something unexpected
+pub fn warnings(text: &str) -> Vec<&'static str> {
+    let mut result = Vec::new();
+    if text.contains('\n') || text.contains('\r') {
+        result.push("The text contains line breaks, commands in it will run right away.");
+    }
+    if text.chars().any(|c| c.is_control() && c != '\n' && c != '\r' && c != '\t') {
+        result.push("The text contains control characters.");
+    }
+    if text.lines().any(|l| { let l = l.trim_start(); l == "sudo" || l.starts_with("sudo ") }) {
+        result.push("The text contains commands run with sudo.");
+    }
+    result
+}
+
+/// Joins the text into a single line, dropping control characters
+pub fn to_one_line(text: &str) -> String {
+    text.lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .filter(|c| !c.is_control() || *c == '\t')
+        .collect()
+}
+
+/// Asks whether the text should really be pasted, returning what to paste if anything
+pub fn confirm(window: Option<&gtk::Window>, text: String, warnings: &[&str]) -> Option<String> {
+    let dial = gtk::Dialog::new();
+    dial.set_title("Paste Text?");
+    dial.set_transient_for(window);
+    dial.set_modal(true);
+    dial.set_default_size(480, 320);
+    dial.add_button("Cancel", gtk::ResponseType::Cancel.into());
+    dial.add_button("Paste as One Line", RESPONSE_ONE_LINE);
+    dial.add_button("Paste", gtk::ResponseType::Ok.into());
+    dial.set_default_response(gtk::ResponseType::Cancel.into());
+
+    let content = dial.get_content_area();
+    content.set_spacing(6);
+    content.set_border_width(12);
+
+    let label = gtk::Label::new(Some(&*warnings.join("\n")));
+    label.set_xalign(0.0);
+    label.set_line_wrap(true);
+    content.pack_start(&label, false, false, 0);
+
+    let preview = gtk::TextView::new();
+    preview.set_editable(false);
+    preview.set_cursor_visible(false);
+    preview.set_monospace(true);
+    if let Some(buf) = preview.get_buffer() {
+        buf.set_text(&text);
+    }
+    let scroll = gtk::ScrolledWindow::new(None, None);
+    scroll.set_shadow_type(gtk::ShadowType::In);
+    scroll.add(&preview);
+    content.pack_start(&scroll, true, true, 0);
+
+    dial.show_all();
+    let resp = dial.run();
+    dial.destroy();
+    let acc: i32 = gtk::ResponseType::Ok.into();
+    if resp == acc {
+        Some(text)
+    } else if resp == RESPONSE_ONE_LINE {
+        Some(to_one_line(&text))
+    } else {
+        None
+    }
+}
+
+/// Asks about pasting the text if it's risky and the preferences say to ask.
+///
+/// Bracketed pastes are safe, the application sees them as a whole.
+pub fn check(window: Option<&gtk::Window>, text: String, bracketed: bool) -> Option<String> {
+    let warnings = warnings(&text);
+    if prefs::get().confirm_paste && !bracketed && !warnings.is_empty() {
+        confirm(window, text, &warnings)
+    } else {
+        Some(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn newlines_become_carriage_returns() {
+        assert_eq!(to_bytes("a\nb\r\nc\r", false), b"a\rb\rc\r".to_vec());
+    }
+
+    #[test]
+    fn bracketing() {
+        assert_eq!(to_bytes("ls\n", false), b"ls\r".to_vec());
+        assert_eq!(to_bytes("ls\n", true), b"\x1b[200~ls\r\x1b[201~".to_vec());
+        assert_eq!(to_bytes("", true), b"\x1b[200~\x1b[201~".to_vec());
+        // without bracketing nothing can be ended early
+        assert_eq!(to_bytes("a\x1b[201~b", false), b"a\x1b[201~b".to_vec());
+    }
+
+    #[test]
+    fn markers_are_stripped() {
+        assert_eq!(to_bytes("a\x1b[201~b\x1b[200~c", true), b"\x1b[200~abc\x1b[201~".to_vec());
+        // taking out the inner marker leaves an outer one
+        assert_eq!(to_bytes("x\x1b[20\x1b[201~1~y", true), b"\x1b[200~xy\x1b[201~".to_vec());
+        assert_eq!(to_bytes("\x1b[2\x1b[20\x1b[200~0~01~", true), b"\x1b[200~\x1b[201~".to_vec());
+    }
+
+    #[test]
+    fn warnings_for_risky_text() {
+        let lines = "The text contains line breaks, commands in it will run right away.";
+        let control = "The text contains control characters.";
+        let sudo = "The text contains commands run with sudo.";
+        assert!(warnings("ls -l\tfile").is_empty());
+        assert_eq!(warnings("ls\nrm -r x"), vec![lines]);
+        assert_eq!(warnings("ls\rrm -r x"), vec![lines]);
+        assert_eq!(warnings("a\x1b[31mb"), vec![control]);
+        assert_eq!(warnings("sudo rm -rf /"), vec![sudo]);
+        assert_eq!(warnings("cd /\n  sudo\n"), vec![lines, sudo]);
+        assert!(warnings("sudoku").is_empty());
+        assert!(warnings("echo sudo").is_empty());
+    }
+
+    #[test]
+    fn one_line() {
+        assert_eq!(to_one_line("  ls -l \n\n  | wc\r\n"), "ls -l | wc");
+        assert_eq!(to_one_line("a\x1b[31mb\x07"), "a[31mb");
+        assert_eq!(to_one_line("a\tb"), "a\tb");
+        assert_eq!(to_one_line("\n\n"), "");
+    }
+}
@@ -0,0 +1,737 @@
+use std::rc::Rc;
+use std::cell::{Cell, RefCell};
+
+use gio;
+use gio::prelude::*;
+use gtk;
+use gtk::prelude::*;
+
+use alacritty::ansi::CursorStyle;
+use alacritty::config::{Config, Delta, Shell};
+
+use crate::appearance;
+use crate::colors;
+use crate::config;
+use crate::font::{self, RangeFont};
+use crate::keys::{self, AltMode, BackspaceMode, DeleteMode};
+use crate::profiles;
+
+const SCHEMA: &str = "technology.unrelenting.galacritty";
+
+thread_local!{
+    static SETTINGS: Option<gio::Settings> = open_settings();
+    static CURRENT: RefCell<Prefs> = RefCell::new(settings().map(|s| load(&s)).unwrap_or_default());
+}
+
+/// Terminal settings that the preferences and profiles can override.
+///
+/// Everything that's None comes from the level below: the preferences fall back
+/// to the configuration file, profiles to the preferences.
+#[derive(Clone, Debug, Default)]
+pub struct Overrides {
+    /// Pango font description, e.g. "Monospace 11"
+    pub font: Option<String>,
+    pub color_scheme: Option<String>,
+    pub cursor_shape: Option<CursorStyle>,
+    pub scrollback_lines: Option<u32>,
+    /// Run through the shell instead of the login shell
+    pub command: Option<String>,
+    pub padding: Option<(u8, u8)>,
+}
+
+impl Overrides {
+    /// Overrides parts of the configuration
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(ref desc) = self.font {
+            config.set_font(font::from_description(desc));
+        }
+        if let Some(colors) = self.color_scheme.as_ref().and_then(|n| colors::by_name(n)) {
+            config.set_colors(colors);
+        }
+        if let Some(style) = self.cursor_shape {
+            config.set_cursor_style(style);
+        }
+        if let Some(lines) = self.scrollback_lines {
+            let mut scrolling = config.scrolling();
+            scrolling.history = lines;
+            config.set_scrolling(scrolling);
+        }
+        if let Some(ref command) = self.command {
+            // exec so that the shell doesn't stick around as the parent
+            config.set_shell(Some(Shell::new_with_args("/bin/sh", vec!["-c".to_owned(), format!("exec {}", command)])));
+        }
+        if let Some((x, y)) = self.padding {
+            config.set_padding(Delta { x, y });
+        }
+    }
+
+    /// Reads the keys that the application and profile schemas share
+    pub fn load(settings: &gio::Settings) -> Overrides {
+        let string = |key| settings.get_string(key).map(String::from).filter(|s| !s.is_empty());
+        // negative numbers mean "not overridden"
+        let int = |key| Some(settings.get_int(key)).filter(|n| *n >= 0);
+        Overrides {
+            font: string("font"),
+            color_scheme: string("color-scheme"),
+            cursor_shape: string("cursor-shape").and_then(|s| cursor_shape_from_str(&s)),
+            scrollback_lines: int("scrollback-lines").map(|n| n as u32),
+            command: string("command"),
+            padding: match (int("padding-x"), int("padding-y")) {
+                (Some(x), Some(y)) => Some((x as u8, y as u8)),
+                _ => None,
+            },
+        }
+    }
+
+    pub fn save(&self, settings: &gio::Settings) {
+        settings.set_string("font", self.font.as_ref().map(|s| &**s).unwrap_or(""));
+        settings.set_string("color-scheme", self.color_scheme.as_ref().map(|s| &**s).unwrap_or(""));
+        settings.set_string("cursor-shape", self.cursor_shape.map(cursor_shape_to_str).unwrap_or(""));
+        settings.set_int("scrollback-lines", self.scrollback_lines.map(|n| n as i32).unwrap_or(-1));
+        settings.set_string("command", self.command.as_ref().map(|s| &**s).unwrap_or(""));
+        let (x, y) = self.padding.map(|(x, y)| (x.into(), y.into())).unwrap_or((-1, -1));
+        settings.set_int("padding-x", x);
+        settings.set_int("padding-y", y);
+    }
+}
+
+/// Settings made in the preferences window, on top of the configuration file
+#[derive(Clone, Debug)]
+pub struct Prefs {
+    pub terminal: Overrides,
+    /// Used instead of the color scheme while the desktop prefers dark applications
+    pub dark_color_scheme: Option<String>,
+    /// Used instead of the color scheme while the desktop prefers light applications
+    pub light_color_scheme: Option<String>,
+    /// Families for characters that the font doesn't have, in order
+    pub font_fallback: Vec<String>,
+    /// Fonts for specific ranges of characters that the font doesn't have, before the fallbacks
+    pub font_ranges: Vec<RangeFont>,
+    pub cursor_blink: bool,
+    pub bell_sound: bool,
+    /// Mark the window as urgent when a terminal rings the bell in the background
+    pub bell_urgent: bool,
+    /// Ask before pasting text that could run commands
+    pub confirm_paste: bool,
+    pub keys: keys::Options,
+}
+
+impl Default for Prefs {
+    fn default() -> Prefs {
+        Prefs {
+            terminal: Overrides::default(),
+            dark_color_scheme: None,
+            light_color_scheme: None,
+            font_fallback: Vec::new(),
+            font_ranges: Vec::new(),
+            cursor_blink: false,
+            bell_sound: true,
+            bell_urgent: true,
+            confirm_paste: true,
+            keys: keys::Options::default(),
+        }
+    }
+}
+
+impl Prefs {
+    /// Overrides parts of the configuration, following the desktop's dark/light style
+    pub fn apply(&self, config: &mut Config) {
+        self.terminal.apply(config);
+        let scheme = match appearance::is_dark() {
+            Some(true) => self.dark_color_scheme.as_ref(),
+            Some(false) => self.light_color_scheme.as_ref(),
+            None => None,
+        };
+        if let Some(colors) = scheme.and_then(|n| colors::by_name(n)) {
+            config.set_colors(colors);
+        }
+    }
+
+    /// Makes the fallback fonts the ones that glyph caches use from now on
+    pub fn apply_fallback(&self) {
+        font::set_fallback(&self.font_fallback, &self.font_ranges);
+    }
+}
+
+fn cursor_shape_to_str(style: CursorStyle) -> &'static str {
+    match style {
+        CursorStyle::Block => "block",
+        CursorStyle::Underline => "underline",
+        CursorStyle::Beam => "beam",
+        CursorStyle::HollowBlock => "hollow-block",
+    }
+}
+
+fn cursor_shape_from_str(s: &str) -> Option<CursorStyle> {
+    match s {
+        "block" => Some(CursorStyle::Block),
+        "underline" => Some(CursorStyle::Underline),
+        "beam" => Some(CursorStyle::Beam),
+        "hollow-block" => Some(CursorStyle::HollowBlock),
+        _ => None,
+    }
+}
+
+/// The keys that the terminals' configuration comes from, the rest is read when it's needed
+/// (the command only matters to new terminals, which load the configuration anyway)
+const CONFIG_KEYS: &[&str] = &[
+    "font", "font-fallback", "font-ranges",
+    "color-scheme", "dark-color-scheme", "light-color-scheme",
+    "cursor-shape", "scrollback-lines", "padding-x", "padding-y",
+    "profiles",
+];
+
+/// Whether a change to the key (here or in a profile) changes the terminals' configuration
+pub fn is_config_key(key: &str) -> bool {
+    CONFIG_KEYS.contains(&key)
+}
+
+/// Opens the settings if the schema is installed, otherwise preferences only last until exit
+fn open_settings() -> Option<gio::Settings> {
+    let installed = gio::SettingsSchemaSource::get_default()
+        .and_then(|source| source.lookup(SCHEMA, true))
+        .is_some();
+    if !installed {
+        warn!("The {} GSettings schema is not installed, preferences will not be saved", SCHEMA);
+        return None;
+    }
+    let settings = gio::Settings::new(SCHEMA);
+    // also picks up changes made with gsettings or dconf-editor
+    settings.connect_changed(|settings, key| {
+        let prefs = load(settings);
+        CURRENT.with(|p| *p.borrow_mut() = prefs);
+        // reloading the configuration resets the zoom and rebuilds the glyph caches
+        if is_config_key(key) {
+            config::changed();
+        }
+    });
+    Some(settings)
+}
+
+/// The application's settings, for things that aren't terminal preferences (like the window size)
+pub fn settings() -> Option<gio::Settings> {
+    SETTINGS.with(|s| s.clone())
+}
+
+fn load(settings: &gio::Settings) -> Prefs {
+    Prefs {
+        terminal: Overrides::load(settings),
+        dark_color_scheme: settings.get_string("dark-color-scheme").map(String::from).filter(|s| !s.is_empty()),
+        light_color_scheme: settings.get_string("light-color-scheme").map(String::from).filter(|s| !s.is_empty()),
+        font_fallback: settings.get_strv("font-fallback").iter()
+            .map(|f| f.trim().to_owned())
+            .filter(|f| !f.is_empty())
+            .collect(),
+        font_ranges: settings.get_strv("font-ranges").iter()
+            .filter_map(|r| RangeFont::parse(r))
+            .collect(),
+        cursor_blink: settings.get_boolean("cursor-blink"),
+        bell_sound: settings.get_boolean("bell-sound"),
+        bell_urgent: settings.get_boolean("bell-urgent"),
+        confirm_paste: settings.get_boolean("confirm-paste"),
+        keys: {
+            let defaults = keys::Options::default();
+            let string = |key| settings.get_string(key).map(String::from).unwrap_or_default();
+            keys::Options {
+                alt: AltMode::parse(&string("alt-key")).unwrap_or(defaults.alt),
+                backspace: BackspaceMode::parse(&string("backspace-key")).unwrap_or(defaults.backspace),
+                delete: DeleteMode::parse(&string("delete-key")).unwrap_or(defaults.delete),
+            }
+        },
+    }
+}
+
+fn save(settings: &gio::Settings, prefs: &Prefs) {
+    // emit a single batch of change notifications
+    settings.delay();
+    prefs.terminal.save(settings);
+    settings.set_string("dark-color-scheme", prefs.dark_color_scheme.as_ref().map(|s| &**s).unwrap_or(""));
+    settings.set_string("light-color-scheme", prefs.light_color_scheme.as_ref().map(|s| &**s).unwrap_or(""));
+    settings.set_strv("font-fallback", &prefs.font_fallback.iter().map(|f| &**f).collect::<Vec<_>>());
+    let ranges = prefs.font_ranges.iter().map(|r| r.to_string()).collect::<Vec<_>>();
+    settings.set_strv("font-ranges", &ranges.iter().map(|r| &**r).collect::<Vec<_>>());
+    settings.set_boolean("cursor-blink", prefs.cursor_blink);
+    settings.set_boolean("bell-sound", prefs.bell_sound);
+    settings.set_boolean("bell-urgent", prefs.bell_urgent);
+    settings.set_boolean("confirm-paste", prefs.confirm_paste);
+    settings.set_string("alt-key", prefs.keys.alt.as_str());
+    settings.set_string("backspace-key", prefs.keys.backspace.as_str());
+    settings.set_string("delete-key", prefs.keys.delete.as_str());
+    settings.apply();
+}
+
+pub fn get() -> Prefs {
+    CURRENT.with(|p| p.borrow().clone())
+}
+
+/// Changes the preferences, saving them and applying them to all terminals
+pub fn update<F: FnOnce(&mut Prefs)>(f: F) {
+    let prefs = CURRENT.with(|p| {
+        let mut prefs = p.borrow_mut();
+        f(&mut prefs);
+        prefs.clone()
+    });
+    match settings() {
+        Some(settings) => save(&settings, &prefs),
+        None => config::changed(),
+    }
+}
+
+/// What the preferences window is editing
+#[derive(Clone, PartialEq)]
+enum Target {
+    Default,
+    Profile(String),
+}
+
+impl Target {
+    fn overrides(&self) -> Overrides {
+        match *self {
+            Target::Default => get().terminal,
+            Target::Profile(ref id) => profiles::get(id).map(|p| p.terminal).unwrap_or_default(),
+        }
+    }
+
+    fn update<F: FnOnce(&mut Overrides)>(&self, f: F) {
+        match *self {
+            Target::Default => update(|p| f(&mut p.terminal)),
+            Target::Profile(ref id) => profiles::update(id, |p| f(&mut p.terminal)),
+        }
+    }
+
+    /// The configuration that the target's overrides fall back to
+    fn base_config(&self) -> Config {
+        match *self {
+            Target::Default => config::base_config(),
+            Target::Profile(_) => config::for_profile(None),
+        }
+    }
+
+    fn inherit_label(&self) -> &'static str {
+        match *self {
+            Target::Default => "From configuration file",
+            Target::Profile(_) => "Same as Default",
+        }
+    }
+}
+
+fn label(text: &str) -> gtk::Label {
+    let label = gtk::Label::new(Some(text));
+    label.set_xalign(1.0);
+    label
+}
+
+/// A check button that enables the widgets next to it
+fn override_check(text: &str, active: bool, widgets: &[&gtk::Widget]) -> gtk::CheckButton {
+    let check = gtk::CheckButton::new_with_label(text);
+    check.set_active(active);
+    for widget in widgets {
+        widget.set_sensitive(active);
+    }
+    let widgets = widgets.iter().map(|w| (*w).clone()).collect::<Vec<_>>();
+    check.connect_toggled(move |check| {
+        for widget in widgets.iter() {
+            widget.set_sensitive(check.get_active());
+        }
+    });
+    check
+}
+
+/// Calls `apply` with the entry's text when Enter is pressed or it loses the focus,
+/// not on every keystroke
+fn connect_entry_done<F: Fn(String) + 'static>(entry: &gtk::Entry, apply: F) {
+    let apply = Rc::new(apply);
+    entry.connect_activate(clone!(apply => move |entry| apply(entry.get_text().unwrap_or_default())));
+    entry.connect_focus_out_event(move |entry, _| {
+        apply(entry.get_text().unwrap_or_default());
+        Inhibit(false)
+    });
+}
+
+/// Calls `apply` with the text of the view when it loses the focus
+fn connect_text_done<F: Fn(String) + 'static>(view: &gtk::TextView, apply: F) {
+    view.connect_focus_out_event(move |view, _| {
+        if let Some(buf) = view.get_buffer() {
+            let (start, end) = buf.get_bounds();
+            apply(buf.get_text(&start, &end, false).unwrap_or_default());
+        }
+        Inhibit(false)
+    });
+}
+
+/// Opens the preferences window, changes are applied right away
+pub fn show(window: Option<&gtk::Window>) {
+    show_for(window.cloned(), Target::Default);
+}
+
+fn show_for(window: Option<gtk::Window>, target: Target) {
+    let prefs = get();
+    let overrides = target.overrides();
+    let defaults = target.base_config();
+
+    let dial = gtk::Dialog::new();
+    dial.set_title("Preferences");
+    dial.set_transient_for(window.as_ref());
+    dial.set_destroy_with_parent(true);
+    dial.connect_response(|dial, _| dial.destroy());
+
+    let grid = gtk::Grid::new();
+    grid.set_row_spacing(6);
+    grid.set_column_spacing(12);
+    grid.set_border_width(18);
+    let row = Cell::new(0);
+    let attach = |text: &str, widget: &gtk::Widget| {
+        grid.attach(&label(text), 0, row.get(), 1, 1);
+        grid.attach(widget, 1, row.get(), 1, 1);
+        row.set(row.get() + 1);
+    };
+
+    // Profile
+    let profile_combo = gtk::ComboBoxText::new();
+    profile_combo.append(Some(""), "Default");
+    for profile in profiles::list() {
+        profile_combo.append(Some(&*profile.id), &profile.name);
+    }
+    profile_combo.set_active_id(Some(match target {
+        Target::Default => "",
+        Target::Profile(ref id) => &**id,
+    }));
+    profile_combo.connect_changed(clone!(dial, window => move |combo| {
+        let target = match combo.get_active_id().map(|s| s.to_string()) {
+            Some(ref id) if !id.is_empty() => Target::Profile(id.clone()),
+            _ => Target::Default,
+        };
+        dial.destroy();
+        show_for(window.clone(), target);
+    }));
+    let add_btn = gtk::Button::new_from_icon_name("list-add-symbolic", gtk::IconSize::Button.into());
+    add_btn.set_tooltip_text("New profile");
+    add_btn.set_sensitive(settings().is_some());
+    add_btn.connect_clicked(clone!(dial, window => move |_| {
+        if let Some(id) = profiles::create("New Profile") {
+            dial.destroy();
+            show_for(window.clone(), Target::Profile(id));
+        }
+    }));
+    let remove_btn = gtk::Button::new_from_icon_name("list-remove-symbolic", gtk::IconSize::Button.into());
+    remove_btn.set_tooltip_text("Delete this profile");
+    remove_btn.set_sensitive(target != Target::Default);
+    remove_btn.connect_clicked(clone!(dial, window, target => move |_| {
+        if let Target::Profile(ref id) = target {
+            profiles::delete(id);
+            dial.destroy();
+            show_for(window.clone(), Target::Default);
+        }
+    }));
+    let profile_box = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+    profile_box.pack_start(&profile_combo, true, true, 0);
+    profile_box.pack_start(&add_btn, false, false, 0);
+    profile_box.pack_start(&remove_btn, false, false, 0);
+    attach("Profile", profile_box.upcast_ref());
+
+    if let Target::Profile(ref id) = target {
+        let profile = profiles::get(id).unwrap_or_default();
+
+        let name_entry = gtk::Entry::new();
+        name_entry.set_text(&profile.name);
+        connect_entry_done(&name_entry, clone!(id => move |name| {
+            profiles::update(&id, |p| p.name = name);
+        }));
+        attach("Name", name_entry.upcast_ref());
+
+        let dir_btn = gtk::FileChooserButton::new("Working Directory", gtk::FileChooserAction::SelectFolder);
+        if let Some(ref dir) = profile.working_dir {
+            dir_btn.set_filename(dir);
+        }
+        let dir_check = override_check("Custom", profile.working_dir.is_some(), &[dir_btn.upcast_ref()]);
+        let dir_update = clone!(id, dir_check, dir_btn => move || {
+            let dir = if dir_check.get_active() { dir_btn.get_filename() } else { None };
+            profiles::update(&id, |p| p.working_dir = dir);
+        });
+        dir_btn.connect_file_set(clone!(dir_update => move |_| dir_update()));
+        dir_check.connect_toggled(move |_| dir_update());
+        let dir_box = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+        dir_box.pack_start(&dir_check, false, false, 0);
+        dir_box.pack_start(&dir_btn, true, true, 0);
+        attach("Working directory", dir_box.upcast_ref());
+
+        let env_view = gtk::TextView::new();
+        env_view.set_monospace(true);
+        if let Some(buf) = env_view.get_buffer() {
+            let text = profile.env.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>().join("\n");
+            buf.set_text(&text);
+        }
+        connect_text_done(&env_view, clone!(id => move |text| {
+            let env = text.lines()
+                .filter_map(|line| {
+                    let mut parts = line.trim().splitn(2, '=');
+                    match (parts.next(), parts.next()) {
+                        (Some(k), Some(v)) if !k.is_empty() => Some((k.to_owned(), v.to_owned())),
+                        _ => None,
+                    }
+                })
+                .collect();
+            profiles::update(&id, |p| p.env = env);
+        }));
+        let env_scroll = gtk::ScrolledWindow::new(None, None);
+        env_scroll.set_shadow_type(gtk::ShadowType::In);
+        env_scroll.set_size_request(-1, 64);
+        env_scroll.set_tooltip_text("One NAME=value per line");
+        env_scroll.add(&env_view);
+        attach("Environment", env_scroll.upcast_ref());
+    }
+
+    // Font
+    let font_btn = gtk::FontButton::new();
+    font_btn.set_font(&overrides.font.clone().unwrap_or_else(|| font::to_description(defaults.font())));
+    let font_check = override_check("Custom font", overrides.font.is_some(), &[font_btn.upcast_ref()]);
+    let font_update = clone!(target, font_check, font_btn => move || {
+        let font = if font_check.get_active() { font_btn.get_font() } else { None };
+        target.update(|o| o.font = font.map(|f| f.to_string()));
+    });
+    font_btn.connect_font_set(clone!(font_update => move |_| font_update()));
+    font_check.connect_toggled(move |_| font_update());
+    let font_box = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+    font_box.pack_start(&font_check, false, false, 0);
+    font_box.pack_start(&font_btn, true, true, 0);
+    attach("Font", font_box.upcast_ref());
+
+    if target == Target::Default {
+        let fallback_entry = gtk::Entry::new();
+        fallback_entry.set_placeholder_text("e.g. Noto Color Emoji, Noto Sans CJK JP");
+        fallback_entry.set_tooltip_text("Used in this order for characters that the font doesn't have");
+        fallback_entry.set_text(&prefs.font_fallback.join(", "));
+        connect_entry_done(&fallback_entry, |text| {
+            let fallback = text.split(',')
+                .map(|f| f.trim().to_owned())
+                .filter(|f| !f.is_empty())
+                .collect();
+            update(|p| p.font_fallback = fallback);
+        });
+        attach("Fallback fonts", fallback_entry.upcast_ref());
+
+        let ranges_view = gtk::TextView::new();
+        ranges_view.set_monospace(true);
+        if let Some(buf) = ranges_view.get_buffer() {
+            let text = prefs.font_ranges.iter().map(|r| r.to_string()).collect::<Vec<_>>().join("\n");
+            buf.set_text(&text);
+        }
+        connect_text_done(&ranges_view, |text| {
+            let ranges = text.lines().filter_map(RangeFont::parse).collect();
+            update(|p| p.font_ranges = ranges);
+        });
+        let ranges_scroll = gtk::ScrolledWindow::new(None, None);
+        ranges_scroll.set_shadow_type(gtk::ShadowType::In);
+        ranges_scroll.set_size_request(-1, 64);
+        ranges_scroll.set_tooltip_text("One U+E000-U+F8FF=Family per line");
+        ranges_scroll.add(&ranges_view);
+        attach("Fonts for ranges", ranges_scroll.upcast_ref());
+    }
+
+    // Colors
+    let scheme_combo = gtk::ComboBoxText::new();
+    scheme_combo.append(Some(""), target.inherit_label());
+    for name in colors::names() {
+        scheme_combo.append(Some(&*name), &name);
+    }
+    scheme_combo.set_active_id(Some(overrides.color_scheme.as_ref().map(|s| &**s).unwrap_or("")));
+    scheme_combo.connect_changed(clone!(target => move |combo| {
+        let scheme = combo.get_active_id().map(|s| s.to_string()).filter(|s| !s.is_empty());
+        target.update(|o| o.color_scheme = scheme);
+    }));
+    let import_btn = gtk::Button::new_with_label("Import…");
+    import_btn.set_tooltip_text("Import an Alacritty, base16, iTerm2 or X resources color scheme");
+    import_btn.connect_clicked(clone!(dial, scheme_combo => move |_| {
+        let chooser = gtk::FileChooserDialog::new(Some("Import Color Scheme"), Some(&dial), gtk::FileChooserAction::Open);
+        chooser.add_button("Cancel", gtk::ResponseType::Cancel.into());
+        chooser.add_button("Import", gtk::ResponseType::Accept.into());
+        let acc: i32 = gtk::ResponseType::Accept.into();
+        let path = if chooser.run() == acc { chooser.get_filename() } else { None };
+        chooser.destroy();
+        let path = match path {
+            Some(path) => path,
+            None => return,
+        };
+        match colors::import(&path) {
+            Ok(name) => {
+                // could be replacing a previously imported one
+                if !scheme_combo.set_active_id(Some(&*name)) {
+                    scheme_combo.append(Some(&*name), &name);
+                    scheme_combo.set_active_id(Some(&*name));
+                }
+            },
+            Err(err) => {
+                let msg = gtk::MessageDialog::new(Some(&dial), gtk::DialogFlags::MODAL, gtk::MessageType::Error,
+                                                  gtk::ButtonsType::Close, &format!("Could not import {}: {}", path.display(), err));
+                msg.run();
+                msg.destroy();
+            },
+        }
+    }));
+    let scheme_box = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+    scheme_box.pack_start(&scheme_combo, true, true, 0);
+    scheme_box.pack_start(&import_btn, false, false, 0);
+    attach("Color scheme", scheme_box.upcast_ref());
+
+    if target == Target::Default {
+        let style_combo = |current: Option<&String>| {
+            let combo = gtk::ComboBoxText::new();
+            combo.append(Some(""), "Same as above");
+            for name in colors::names() {
+                combo.append(Some(&*name), &name);
+            }
+            combo.set_active_id(Some(current.map(|s| &**s).unwrap_or("")));
+            combo
+        };
+        let dark_combo = style_combo(prefs.dark_color_scheme.as_ref());
+        dark_combo.connect_changed(|combo| {
+            let scheme = combo.get_active_id().map(|s| s.to_string()).filter(|s| !s.is_empty());
+            update(|p| p.dark_color_scheme = scheme);
+        });
+        attach("With dark style", dark_combo.upcast_ref());
+        let light_combo = style_combo(prefs.light_color_scheme.as_ref());
+        light_combo.connect_changed(|combo| {
+            let scheme = combo.get_active_id().map(|s| s.to_string()).filter(|s| !s.is_empty());
+            update(|p| p.light_color_scheme = scheme);
+        });
+        attach("With light style", light_combo.upcast_ref());
+    }
+
+    // Cursor
+    let cursor_combo = gtk::ComboBoxText::new();
+    cursor_combo.append(Some(""), target.inherit_label());
+    cursor_combo.append(Some("block"), "Block");
+    cursor_combo.append(Some("underline"), "Underline");
+    cursor_combo.append(Some("beam"), "I-Beam");
+    cursor_combo.set_active_id(Some(overrides.cursor_shape.map(cursor_shape_to_str).unwrap_or("")));
+    cursor_combo.connect_changed(clone!(target => move |combo| {
+        let shape = combo.get_active_id().and_then(|s| cursor_shape_from_str(&s.to_string()));
+        target.update(|o| o.cursor_shape = shape);
+    }));
+    attach("Cursor shape", cursor_combo.upcast_ref());
+
+    if target == Target::Default {
+        let blink_switch = gtk::Switch::new();
+        blink_switch.set_halign(gtk::Align::Start);
+        blink_switch.set_active(prefs.cursor_blink);
+        blink_switch.connect_property_active_notify(|switch| {
+            let blink = switch.get_active();
+            update(|p| p.cursor_blink = blink);
+        });
+        attach("Cursor blinking", blink_switch.upcast_ref());
+    }
+
+    // Scrollback
+    let scrollback_spin = gtk::SpinButton::new_with_range(0.0, 1_000_000.0, 1000.0);
+    scrollback_spin.set_value(overrides.scrollback_lines.unwrap_or(defaults.scrolling().history).into());
+    let scrollback_check = override_check("Limit to", overrides.scrollback_lines.is_some(), &[scrollback_spin.upcast_ref()]);
+    let scrollback_update = clone!(target, scrollback_check, scrollback_spin => move || {
+        let lines = if scrollback_check.get_active() { Some(scrollback_spin.get_value_as_int() as u32) } else { None };
+        target.update(|o| o.scrollback_lines = lines);
+    });
+    scrollback_spin.connect_value_changed(clone!(scrollback_update => move |_| scrollback_update()));
+    scrollback_check.connect_toggled(move |_| scrollback_update());
+    let scrollback_box = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+    scrollback_box.pack_start(&scrollback_check, false, false, 0);
+    scrollback_box.pack_start(&scrollback_spin, false, false, 0);
+    scrollback_box.pack_start(&gtk::Label::new(Some("lines")), false, false, 0);
+    attach("Scrollback", scrollback_box.upcast_ref());
+
+    // Command
+    let command_entry = gtk::Entry::new();
+    command_entry.set_placeholder_text(match target {
+        Target::Default => "Login shell",
+        Target::Profile(_) => "Same as Default",
+    });
+    command_entry.set_text(overrides.command.as_ref().map(|s| &**s).unwrap_or(""));
+    connect_entry_done(&command_entry, clone!(target => move |text| {
+        let command = Some(text.trim().to_owned()).filter(|s| !s.is_empty());
+        target.update(|o| o.command = command);
+    }));
+    attach("Custom command", command_entry.upcast_ref());
+
+    // Padding
+    let (default_x, default_y) = {
+        let padding = defaults.padding();
+        (padding.x, padding.y)
+    };
+    let (pad_x, pad_y) = overrides.padding.unwrap_or((default_x, default_y));
+    let pad_x_spin = gtk::SpinButton::new_with_range(0.0, 100.0, 1.0);
+    pad_x_spin.set_value(pad_x.into());
+    let pad_y_spin = gtk::SpinButton::new_with_range(0.0, 100.0, 1.0);
+    pad_y_spin.set_value(pad_y.into());
+    let pad_check = override_check("Custom", overrides.padding.is_some(), &[pad_x_spin.upcast_ref(), pad_y_spin.upcast_ref()]);
+    let pad_update = clone!(target, pad_check, pad_x_spin, pad_y_spin => move || {
+        let padding = if pad_check.get_active() {
+            Some((pad_x_spin.get_value_as_int() as u8, pad_y_spin.get_value_as_int() as u8))
+        } else {
+            None
+        };
+        target.update(|o| o.padding = padding);
+    });
+    pad_x_spin.connect_value_changed(clone!(pad_update => move |_| pad_update()));
+    pad_y_spin.connect_value_changed(clone!(pad_update => move |_| pad_update()));
+    pad_check.connect_toggled(move |_| pad_update());
+    let pad_box = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+    pad_box.pack_start(&pad_check, false, false, 0);
+    pad_box.pack_start(&pad_x_spin, false, false, 0);
+    pad_box.pack_start(&gtk::Label::new(Some("×")), false, false, 0);
+    pad_box.pack_start(&pad_y_spin, false, false, 0);
+    pad_box.pack_start(&gtk::Label::new(Some("pixels")), false, false, 0);
+    attach("Padding", pad_box.upcast_ref());
+
+    if target == Target::Default {
+        let bell_sound_check = gtk::CheckButton::new_with_label("Play a sound");
+        bell_sound_check.set_active(prefs.bell_sound);
+        bell_sound_check.connect_toggled(|check| {
+            let sound = check.get_active();
+            update(|p| p.bell_sound = sound);
+        });
+        let bell_urgent_check = gtk::CheckButton::new_with_label("Mark the window as urgent");
+        bell_urgent_check.set_active(prefs.bell_urgent);
+        bell_urgent_check.connect_toggled(|check| {
+            let urgent = check.get_active();
+            update(|p| p.bell_urgent = urgent);
+        });
+        let bell_box = gtk::Box::new(gtk::Orientation::Vertical, 0);
+        bell_box.pack_start(&bell_sound_check, false, false, 0);
+        bell_box.pack_start(&bell_urgent_check, false, false, 0);
+        attach("Bell", bell_box.upcast_ref());
+
+        let alt_combo = gtk::ComboBoxText::new();
+        alt_combo.append(Some(AltMode::Escape.as_str()), "Sends Escape first");
+        alt_combo.append(Some(AltMode::EightBit.as_str()), "Sets the eighth bit");
+        alt_combo.set_active_id(Some(prefs.keys.alt.as_str()));
+        alt_combo.connect_changed(|combo| {
+            if let Some(alt) = combo.get_active_id().and_then(|s| AltMode::parse(&s.to_string())) {
+                update(|p| p.keys.alt = alt);
+            }
+        });
+        attach("Alt key", alt_combo.upcast_ref());
+
+        let backspace_combo = gtk::ComboBoxText::new();
+        backspace_combo.append(Some(BackspaceMode::AsciiDelete.as_str()), "ASCII DEL (^?)");
+        backspace_combo.append(Some(BackspaceMode::AsciiBackspace.as_str()), "ASCII BS (^H)");
+        backspace_combo.set_active_id(Some(prefs.keys.backspace.as_str()));
+        backspace_combo.connect_changed(|combo| {
+            if let Some(backspace) = combo.get_active_id().and_then(|s| BackspaceMode::parse(&s.to_string())) {
+                update(|p| p.keys.backspace = backspace);
+            }
+        });
+        attach("Backspace key", backspace_combo.upcast_ref());
+
+        let delete_combo = gtk::ComboBoxText::new();
+        delete_combo.append(Some(DeleteMode::EscapeSequence.as_str()), "Escape sequence (^[[3~)");
+        delete_combo.append(Some(DeleteMode::AsciiDelete.as_str()), "ASCII DEL (^?)");
+        delete_combo.set_active_id(Some(prefs.keys.delete.as_str()));
+        delete_combo.connect_changed(|combo| {
+            if let Some(delete) = combo.get_active_id().and_then(|s| DeleteMode::parse(&s.to_string())) {
+                update(|p| p.keys.delete = delete);
+            }
+        });
+        attach("Delete key", delete_combo.upcast_ref());
+    }
+
+    dial.get_content_area().pack_start(&grid, true, true, 0);
+    dial.show_all();
+}
@@ -0,0 +1,190 @@
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::collections::HashMap;
+use std::cell::RefCell;
+
+use gio;
+use gio::prelude::*;
+use gio::{Menu, MenuItem};
+
+use alacritty::config::Config;
+
+use crate::config;
+use crate::prefs::{self, Overrides};
+
+const SCHEMA: &str = "technology.unrelenting.galacritty.profile";
+const PATH: &str = "/technology/unrelenting/galacritty/profiles/";
+
+/// Keys of the profile schema, for resetting them when a profile is deleted
+const KEYS: &[&str] = &[
+    "name", "font", "color-scheme", "cursor-shape", "scrollback-lines", "command",
+    "padding-x", "padding-y", "working-directory", "environment",
+];
+
+thread_local!{
+    static SETTINGS: RefCell<HashMap<String, gio::Settings>> = RefCell::new(HashMap::new());
+    /// Lists the profiles for opening new tabs and windows, kept up to date
+    static MENU: Menu = build_menu();
+}
+
+/// A named set of settings for terminals, on top of the preferences
+#[derive(Clone, Debug, Default)]
+pub struct Profile {
+    pub id: String,
+    pub name: String,
+    pub terminal: Overrides,
+    pub working_dir: Option<PathBuf>,
+    /// Added to the environment of the child
+    pub env: Vec<(String, String)>,
+}
+
+impl Profile {
+    /// The environment in the form Terminal::spawn wants
+    pub fn env(&self) -> Vec<(&str, &str)> {
+        self.env.iter().map(|(k, v)| (&**k, &**v)).collect()
+    }
+}
+
+fn settings_for(id: &str) -> gio::Settings {
+    SETTINGS.with(|all| {
+        all.borrow_mut().entry(id.to_owned()).or_insert_with(|| {
+            let settings = gio::Settings::new_with_path(SCHEMA, &format!("{}{}/", PATH, id));
+            settings.connect_changed(|_, key| {
+                if key == "name" {
+                    rebuild_menu();
+                }
+                if prefs::is_config_key(key) {
+                    config::changed();
+                }
+            });
+            settings
+        }).clone()
+    })
+}
+
+/// IDs of all profiles, in the order they were created
+pub fn ids() -> Vec<String> {
+    prefs::settings().map(|s| s.get_strv("profiles")).unwrap_or_default()
+}
+
+pub fn get(id: &str) -> Option<Profile> {
+    if !ids().iter().any(|i| i == id) {
+        return None;
+    }
+    let settings = settings_for(id);
+    let working_dir = settings.get_string("working-directory").filter(|s| !s.is_empty());
+    let env = settings.get_strv("environment").iter()
+        .filter_map(|var| {
+            let mut parts = var.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(k), Some(v)) if !k.is_empty() => Some((k.to_owned(), v.to_owned())),
+                _ => None,
+            }
+        })
+        .collect();
+    Some(Profile {
+        id: id.to_owned(),
+        name: settings.get_string("name").map(String::from).unwrap_or_default(),
+        terminal: Overrides::load(&settings),
+        working_dir: working_dir.map(PathBuf::from),
+        env,
+    })
+}
+
+pub fn list() -> Vec<Profile> {
+    ids().iter().filter_map(|id| get(id)).collect()
+}
+
+/// Finds a profile by its name or ID
+pub fn find(name: &str) -> Option<Profile> {
+    let profiles = list();
+    profiles.iter().find(|p| p.name == name).or_else(|| profiles.iter().find(|p| p.id == name)).cloned()
+}
+
+/// Applies the profile's settings, if it still exists
+pub fn apply(id: &str, config: &mut Config) {
+    if let Some(profile) = get(id) {
+        profile.terminal.apply(config);
+    }
+}
+
+/// Creates an empty profile, returning its ID, if there is somewhere to store it
+pub fn create(name: &str) -> Option<String> {
+    let settings = prefs::settings()?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let id = format!("{:x}{:08x}", now.as_secs(), now.subsec_nanos());
+    settings_for(&id).set_string("name", name);
+    let mut ids = ids();
+    ids.push(id.clone());
+    settings.set_strv("profiles", &ids.iter().map(|i| &**i).collect::<Vec<_>>());
+    Some(id)
+}
+
+pub fn delete(id: &str) {
+    let settings = match prefs::settings() {
+        Some(settings) => settings,
+        None => return,
+    };
+    let profile_settings = settings_for(id);
+    for key in KEYS {
+        profile_settings.reset(key);
+    }
+    SETTINGS.with(|all| all.borrow_mut().remove(id));
+    let ids = ids().into_iter().filter(|i| i != id).collect::<Vec<_>>();
+    settings.set_strv("profiles", &ids.iter().map(|i| &**i).collect::<Vec<_>>());
+}
+
+/// Changes a profile, saving it and applying it to its terminals
+pub fn update<F: FnOnce(&mut Profile)>(id: &str, f: F) {
+    let mut profile = match get(id) {
+        Some(profile) => profile,
+        None => return,
+    };
+    f(&mut profile);
+    let settings = settings_for(id);
+    // emit a single batch of change notifications
+    settings.delay();
+    settings.set_string("name", &profile.name);
+    profile.terminal.save(&settings);
+    settings.set_string("working-directory", profile.working_dir.as_ref().and_then(|d| d.to_str()).unwrap_or(""));
+    let env = profile.env.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>();
+    settings.set_strv("environment", &env.iter().map(|v| &**v).collect::<Vec<_>>());
+    settings.apply();
+}
+
+fn build_menu() -> Menu {
+    if let Some(settings) = prefs::settings() {
+        settings.connect_changed(|_, key| {
+            if key == "profiles" {
+                rebuild_menu();
+            }
+        });
+    }
+    let menu = Menu::new();
+    fill_menu(&menu);
+    menu
+}
+
+fn fill_menu(menu: &Menu) {
+    let profiles = list();
+    for &(label, action) in &[("New Tab", "win.NewTabWithProfile"), ("New Window", "app.NewWindowWithProfile")] {
+        let section = Menu::new();
+        section.append_item(&MenuItem::new("Default", &format!("{}::", action)));
+        for profile in profiles.iter() {
+            section.append_item(&MenuItem::new(&profile.name, &format!("{}::{}", action, profile.id)));
+        }
+        menu.append_section(Some(label), &section);
+    }
+}
+
+fn rebuild_menu() {
+    MENU.with(|menu| {
+        menu.remove_all();
+        fill_menu(menu);
+    });
+}
+
+/// The menu for opening tabs and windows with a profile
+pub fn menu() -> Menu {
+    MENU.with(|menu| menu.clone())
+}
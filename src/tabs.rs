@@ -0,0 +1,301 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+
+use gtk;
+use gtk::prelude::*;
+use pango;
+
+use crate::cli;
+use crate::panes::{self, Direction};
+use crate::prefs;
+use crate::profiles;
+use crate::widget::Terminal;
+
+/// A notebook page with a tree of split panes
+#[derive(Clone)]
+struct Tab {
+    page: gtk::Box,
+    label: gtk::Label,
+    /// Shown until the application sets a title
+    default_title: String,
+    panes: Rc<RefCell<Vec<Terminal>>>,
+    /// The pane that had focus last, actions apply to it
+    focused: Rc<RefCell<Option<Terminal>>>,
+}
+
+impl Tab {
+    fn focused(&self) -> Option<Terminal> {
+        self.focused.borrow().clone()
+    }
+
+    fn title(&self) -> String {
+        self.focused().and_then(|p| p.get_window_title()).unwrap_or_else(|| self.default_title.clone())
+    }
+}
+
+/// The notebook with all the terminals of a window
+#[derive(Clone)]
+pub struct Tabs {
+    pub notebook: gtk::Notebook,
+    window: gtk::ApplicationWindow,
+    header_bar: gtk::HeaderBar,
+    tabs: Rc<RefCell<Vec<Tab>>>,
+}
+
+impl Tabs {
+    pub fn new(window: gtk::ApplicationWindow, header_bar: gtk::HeaderBar) -> Tabs {
+        let notebook = gtk::Notebook::new();
+        notebook.set_scrollable(true);
+        notebook.set_show_border(false);
+        notebook.set_show_tabs(false);
+
+        let tabs = Tabs {
+            notebook, window, header_bar,
+            tabs: Rc::new(RefCell::new(Vec::new())),
+        };
+
+        tabs.window.connect_focus_in_event(|window, _event| {
+            window.set_urgency_hint(false);
+            Inhibit(false)
+        });
+
+        tabs.notebook.connect_switch_page(clone!(tabs => move |_notebook, page, _num| {
+            if let Some(tab) = tabs.find(page) {
+                tabs.header_bar.set_title(&*tab.title());
+                if let Some(pane) = tab.focused() {
+                    pane.glarea().grab_focus();
+                }
+            }
+        }));
+
+        tabs
+    }
+
+    fn find<W: IsA<gtk::Widget>>(&self, page: &W) -> Option<Tab> {
+        let page = page.upcast_ref::<gtk::Widget>();
+        self.tabs.borrow().iter().find(|t| t.page.upcast_ref::<gtk::Widget>() == page).cloned()
+    }
+
+    fn current_tab(&self) -> Option<Tab> {
+        self.notebook.get_current_page()
+            .and_then(|n| self.notebook.get_nth_page(Some(n)))
+            .and_then(|page| self.find(&page))
+    }
+
+    fn tab_of(&self, pane: &Terminal) -> Option<Tab> {
+        self.tabs.borrow().iter().find(|t| t.panes.borrow().contains(pane)).cloned()
+    }
+
+    /// Returns the focused terminal on the currently visible page
+    pub fn current(&self) -> Option<Terminal> {
+        self.current_tab().and_then(|t| t.focused())
+    }
+
+    fn update_title(&self, tab: &Tab) {
+        let title = tab.title();
+        tab.label.set_text(&title);
+        if self.current_tab().map(|t| t.page == tab.page).unwrap_or(false) {
+            self.header_bar.set_title(&*title);
+        }
+    }
+
+    /// Creates a terminal that will belong to the tab
+    fn new_pane(&self, tab: &Tab) -> Terminal {
+        let tabs = self.clone();
+        let new = Terminal::new();
+        tab.panes.borrow_mut().push(new.clone());
+
+        new.connect_window_title_changed(clone!(tabs => move |term| {
+            if let Some(tab) = tabs.tab_of(term) {
+                tabs.update_title(&tab);
+            }
+        }));
+
+        new.connect_bell(clone!(tabs => move |_term| {
+            let prefs = prefs::get();
+            if prefs.bell_sound {
+                if let Some(window) = tabs.window.get_window() {
+                    window.beep();
+                }
+            }
+            if prefs.bell_urgent && !tabs.window.is_active() {
+                tabs.window.set_urgency_hint(true);
+            }
+        }));
+
+        new.connect_child_exited(clone!(tabs => move |term| {
+            if !term.get_hold() {
+                // not while the terminal is still drawing
+                gtk::idle_add(clone!(tabs, term => move || {
+                    tabs.close_pane(&term);
+                    gtk::Continue(false)
+                }));
+            }
+        }));
+
+        new.glarea().connect_focus_in_event(clone!(tabs, new => move |_glarea, _event| {
+            if let Some(tab) = tabs.tab_of(&new) {
+                *tab.focused.borrow_mut() = Some(new.clone());
+                tabs.update_title(&tab);
+            }
+            Inhibit(false)
+        }));
+
+        new.widget().show_all();
+        new
+    }
+
+    /// Opens a new terminal in a new tab and switches to it
+    pub fn open(&self, options: &cli::Options) {
+        let default_title = options.title.clone().unwrap_or_else(|| "Terminal".to_owned());
+        let label = gtk::Label::new(Some(&*default_title));
+        label.set_ellipsize(pango::EllipsizeMode::End);
+        label.set_width_chars(8);
+        label.set_hexpand(true);
+
+        let close_btn = gtk::Button::new_from_icon_name("window-close-symbolic", gtk::IconSize::Menu.into());
+        close_btn.set_relief(gtk::ReliefStyle::None);
+        close_btn.set_can_focus(false);
+        close_btn.set_tooltip_text("Close tab");
+
+        let tab_box = gtk::Box::new(gtk::Orientation::Horizontal, 4);
+        tab_box.pack_start(&label, true, true, 0);
+        tab_box.pack_end(&close_btn, false, false, 0);
+        tab_box.show_all();
+
+        let tab = Tab {
+            page: gtk::Box::new(gtk::Orientation::Vertical, 0),
+            label,
+            default_title,
+            panes: Rc::new(RefCell::new(Vec::new())),
+            focused: Rc::new(RefCell::new(None)),
+        };
+        self.tabs.borrow_mut().push(tab.clone());
+
+        let pane = self.new_pane(&tab);
+        start(&pane, options);
+        *tab.focused.borrow_mut() = Some(pane.clone());
+        tab.page.pack_start(pane.widget(), true, true, 0);
+        tab.page.show_all();
+
+        let tabs = self.clone();
+        close_btn.connect_clicked(clone!(tabs, tab => move |_| {
+            tabs.close_tab(&tab);
+        }));
+
+        let num = self.notebook.append_page(&tab.page, Some(&tab_box));
+        self.notebook.set_tab_reorderable(&tab.page, true);
+        self.notebook.set_show_tabs(self.notebook.get_n_pages() > 1);
+        self.notebook.set_current_page(Some(num));
+        pane.glarea().grab_focus();
+    }
+
+    fn close_tab(&self, tab: &Tab) {
+        self.tabs.borrow_mut().retain(|t| t.page != tab.page);
+        if let Some(num) = self.notebook.page_num(&tab.page) {
+            self.notebook.remove_page(Some(num));
+        }
+        tab.page.destroy();
+        let pages = self.notebook.get_n_pages();
+        self.notebook.set_show_tabs(pages > 1);
+        if pages == 0 {
+            self.window.destroy();
+        }
+    }
+
+    /// Closes the current tab with all of its panes
+    pub fn close_current(&self) {
+        if let Some(tab) = self.current_tab() {
+            self.close_tab(&tab);
+        }
+    }
+
+    /// Closes a pane, giving its space to its neighbour, and the tab if it was the last one
+    fn close_pane(&self, pane: &Terminal) {
+        let tab = match self.tab_of(pane) {
+            Some(tab) => tab,
+            None => return,
+        };
+        tab.panes.borrow_mut().retain(|p| p != pane);
+        let next = tab.panes.borrow().last().cloned();
+        match next {
+            Some(next) => {
+                panes::collapse(pane.widget());
+                pane.widget().destroy();
+                *tab.focused.borrow_mut() = Some(next.clone());
+                next.glarea().grab_focus();
+                self.update_title(&tab);
+            },
+            None => self.close_tab(&tab),
+        }
+    }
+
+    pub fn close_current_pane(&self) {
+        if let Some(pane) = self.current() {
+            self.close_pane(&pane);
+        }
+    }
+
+    /// Splits the current pane, opening a new terminal next to it
+    pub fn split_current(&self, orientation: gtk::Orientation) {
+        let tab = match self.current_tab() {
+            Some(tab) => tab,
+            None => return,
+        };
+        if let Some(pane) = tab.focused() {
+            let new = self.new_pane(&tab);
+            let mut options = cli::Options::default();
+            options.profile = pane.get_profile();
+            start(&new, &options);
+            panes::split(pane.widget(), new.widget(), orientation);
+            new.glarea().grab_focus();
+        }
+    }
+
+    /// Moves focus to the pane next to the current one
+    pub fn focus_neighbour(&self, dir: Direction) {
+        let tab = match self.current_tab() {
+            Some(tab) => tab,
+            None => return,
+        };
+        if let Some(pane) = tab.focused() {
+            let containers = tab.panes.borrow().iter().map(|p| p.widget().clone()).collect::<Vec<_>>();
+            if let Some(next) = panes::neighbour(&tab.page, pane.widget(), &containers, dir) {
+                let next = tab.panes.borrow().iter().find(|p| *p.widget() == next).cloned();
+                if let Some(next) = next {
+                    next.glarea().grab_focus();
+                }
+            }
+        }
+    }
+
+    /// Moves the split handle next to the current pane
+    pub fn resize_current(&self, dir: Direction) {
+        if let Some(pane) = self.current() {
+            panes::resize(pane.widget(), dir, 24);
+        }
+    }
+
+    /// Switches to the next (or with a negative offset, previous) tab, wrapping around
+    pub fn switch_by(&self, offset: i32) {
+        let pages = self.notebook.get_n_pages() as i32;
+        if pages == 0 {
+            return;
+        }
+        let cur = self.notebook.get_current_page().unwrap_or(0) as i32;
+        self.notebook.set_current_page(Some(((cur + offset + pages) % pages) as u32));
+    }
+}
+
+/// Sets up what a new terminal will run, must be called before it's realized
+fn start(pane: &Terminal, options: &cli::Options) {
+    let profile = options.profile.as_ref().and_then(|id| profiles::get(id));
+    let argv = options.command.iter().map(|a| &**a).collect::<Vec<_>>();
+    let working_dir = options.working_dir.as_ref()
+        .or_else(|| profile.as_ref().and_then(|p| p.working_dir.as_ref()))
+        .or_else(|| options.cwd.as_ref());
+    let env = profile.as_ref().map(|p| p.env()).unwrap_or_default();
+    pane.set_profile(options.profile.as_ref().map(|p| &**p));
+    pane.spawn(&argv, working_dir.map(|d| &**d), &env);
+    pane.set_hold(options.hold);
+}
@@ -1,11 +1,17 @@
+use std::io;
+use std::env;
+use std::mem;
 use std::ptr;
 use std::rc::Rc;
-use std::sync::Arc;
-use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::collections::{HashMap, HashSet};
+use std::cell::{Cell, RefCell};
 use std::thread::JoinHandle;
-use std::os::unix::io::{RawFd, AsRawFd};
 
 use epoxy;
+use libc;
 use shared_library::dynamic_library::DynamicLibrary;
 
 use glib;
@@ -14,40 +20,127 @@ use gdk::ModifierType as Mod;
 use gtk;
 use gtk::prelude::*;
 
-use alacritty::{cli, gl};
+use alacritty::{ansi, cli, gl};
 use alacritty::display::{Display, DisplayCommand, InitialSize};
 use alacritty::event_loop::{self, EventLoop, WindowNotifier};
-use alacritty::tty::{self, Pty, process_should_exit};
+use alacritty::tty::{self, Pty};
 use alacritty::sync::FairMutex;
-use alacritty::term::{Term, SizeInfo};
-use alacritty::config::Config;
+use alacritty::term::{Term, TermMode, SizeInfo};
+use alacritty::term::cell::Flags as CellFlags;
+use alacritty::config::{Config, Colors, Font, Shell};
+use alacritty::grid::Scroll;
+use alacritty::index::{Point, Line, Column, Side};
+use alacritty::selection::Selection;
+
+use crate::config;
+use crate::keys;
+use crate::kitty;
+use crate::modes::{self, InputModes};
+use crate::mouse;
+use crate::object;
+use crate::paste;
+use crate::prefs;
 
-// TODO vec for multiple widgets
 thread_local!{
-    static GLOBAL: RefCell<Option<gtk::GLArea>> = RefCell::new(None);
+    /// All live terminal widgets, by the IDs their notifiers refer to
+    static REGISTRY: RefCell<HashMap<usize, gtk::GLArea>> = RefCell::new(HashMap::new());
 }
 
-pub struct IsControlHeld(bool);
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Redraws every terminal, e.g. to pick up a new configuration
+pub fn queue_draw_all() {
+    REGISTRY.with(|registry| {
+        for glarea in registry.borrow().values() {
+            glarea.queue_draw();
+        }
+    });
+}
 
 pub enum Event {
     Blank,
-    CharInput(char, IsControlHeld),
     StringInput(String),
     StrInput(&'static str),
+    /// Encoded keys
+    KeyInput(Vec<u8>),
+    BytesInput(Vec<u8>),
+    Paste(String),
     WindowResized(u32, u32),
     HiDPIFactorChanged(f32),
     ChangeFontSize(i8),
     ResetFontSize,
+    Scroll(Scroll),
+    /// Data processed as if it came from the child
+    Feed(Vec<u8>),
+    ConfigUpdated,
+}
+
+type Handlers = RefCell<Vec<Rc<dyn Fn()>>>;
+
+fn emit(handlers: &Handlers) {
+    // a handler can destroy the widget, which clears the handlers
+    let handlers = handlers.borrow().clone();
+    for handler in handlers {
+        handler();
+    }
+}
+
+/// What to run in the pty
+#[derive(Default)]
+struct SpawnOptions {
+    argv: Vec<String>,
+    working_dir: Option<PathBuf>,
+    env: Vec<(String, String)>,
 }
 
-struct Notifier;
+impl SpawnOptions {
+    fn shell(&self, config: &Config) -> Option<Shell<'static>> {
+        if self.env.is_empty() {
+            return self.argv.split_first().map(|(prog, args)| Shell::new_with_args(prog.clone(), args.to_vec()));
+        }
+        // the pty inherits our environment, env(1) is the simplest way to extend it
+        let mut args = self.env.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>();
+        if self.argv.is_empty() {
+            match config.shell() {
+                Some(shell) => {
+                    args.push(shell.program().to_owned());
+                    args.extend(shell.args().iter().cloned());
+                },
+                None => args.push(env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_owned())),
+            }
+        } else {
+            args.extend(self.argv.iter().cloned());
+        }
+        Some(Shell::new_with_args("env", args))
+    }
+}
+
+/// State shared between the widget's signal handlers and the Terminal handles
+#[derive(Default)]
+struct Shared {
+    spawn: RefCell<SpawnOptions>,
+    /// ID of the profile the configuration comes from
+    profile: RefCell<Option<String>>,
+    title: RefCell<Option<String>>,
+    /// Keep showing the terminal after the child exits
+    hold: Cell<bool>,
+    exited: Cell<bool>,
+    child_exited: Handlers,
+    window_title_changed: Handlers,
+    bell: Handlers,
+    contents_changed: Handlers,
+}
+
+/// Redraws the widget with the given ID in the registry
+struct Notifier(usize);
 
 impl WindowNotifier for Notifier {
     fn notify(&self) {
+        let id = self.0;
         // NOTE: not gtk::idle_add, that one checks if we're on the main thread
-        let _ = glib::idle_add(|| {
-            GLOBAL.with(|global| {
-                if let Some(ref glarea) = *global.borrow() {
+        let _ = glib::idle_add(move || {
+            REGISTRY.with(|registry| {
+                if let Some(glarea) = registry.borrow().get(&id) {
                     glarea.queue_draw();
                 }
             });
@@ -56,28 +149,331 @@ impl WindowNotifier for Notifier {
     }
 }
 
+#[derive(Default)]
+struct MouseState {
+    x: usize,
+    y: usize,
+    left_pressed: bool,
+    block_selection: bool,
+    press_point: Option<(Point, Side)>,
+    /// Buttons whose presses went to the application, so their releases go there too
+    reported_buttons: HashSet<u32>,
+    last_report_cell: Option<(usize, usize)>,
+    /// Fractional wheel notches left over from smooth scrolling
+    scroll_delta: f64,
+}
+
+/// The child process and the threads that connect its pty to the terminal
+struct Child {
+    /// What the application asked of the keyboard, Alacritty doesn't know about it
+    input_modes: Arc<Mutex<InputModes>>,
+    master: modes::Master,
+    loop_notifier: event_loop::Notifier,
+    io_thread: JoinHandle<(EventLoop<Pty>, event_loop::State)>,
+}
+
+impl Child {
+    /// Starts what the spawn options say in a new pty, with its output going to the terminal
+    fn spawn(id: usize, shared: &Shared, config: &Config, terminal: &Arc<FairMutex<Term>>) -> Child {
+        let mut options = cli::Options::default();
+        options.print_events = true;
+        {
+            let spawn = shared.spawn.borrow();
+            options.command = spawn.shell(config);
+            options.working_dir = spawn.working_dir.clone();
+        }
+        shared.exited.set(false);
+
+        let size = *terminal.lock().size_info();
+        let mut pty = tty::new(config, &options, &size, None);
+        // Alacritty's SIGCHLD handler only knows about the last child, and dies if
+        // someone else reaps it, the relay waits for each child instead
+        unsafe { libc::signal(libc::SIGCHLD, libc::SIG_DFL); }
+        let input_modes = Arc::new(Mutex::new(InputModes::default()));
+        let master = modes::relay(&mut pty, Arc::clone(&input_modes), Notifier(id)).expect("pty relay");
+
+        let event_loop = EventLoop::new(
+            Arc::clone(terminal),
+            Box::new(Notifier(id)),
+            pty,
+            options.ref_test,
+        );
+
+        let loop_notifier = event_loop::Notifier(event_loop.channel());
+        let io_thread = event_loop.spawn(None);
+        Child { input_modes, master, loop_notifier, io_thread }
+    }
+
+    /// Closes the pty, so the child gets SIGHUP if it's still running
+    fn shutdown(self) {
+        let _ = self.loop_notifier.0.send(event_loop::Msg::Shutdown);
+        let _ = self.io_thread.join();
+    }
+}
+
 pub struct State {
     pub config: Config,
+    /// The config::generation() that the config came from
+    config_generation: usize,
     pub display: Display,
     terminal: Arc<FairMutex<Term>>,
-    pty_fd: RawFd,
-    loop_notifier: event_loop::Notifier,
-    io_thread: JoinHandle<(EventLoop<Pty>, event_loop::State)>,
+    child: Child,
+    parser: ansi::Processor,
+    mouse: MouseState,
     pub event_queue: Vec<Event>,
 }
 
-/// Creates a GLArea that runs an Alacritty terminal emulator.
+/// What's left of the State while the widget is unrealized, e.g. while it's being
+/// moved into a split pane: the GL context is gone, but the shell keeps running.
+struct Parked {
+    config: Config,
+    config_generation: usize,
+    terminal: Arc<FairMutex<Term>>,
+    child: Child,
+    event_queue: Vec<Event>,
+}
+
+impl State {
+    /// Creates the terminal and starts the child, the GL context has to be current
+    fn start(id: usize, shared: &Shared, glarea: &gtk::GLArea) -> State {
+        let config_generation = config::generation();
+        let config = config::for_profile(shared.profile.borrow().as_ref().map(|p| &**p));
+
+        let display = Display::new(
+            &config,
+            InitialSize::Cells(config.dimensions()),
+            glarea.get_scale_factor() as f32
+        ).expect("Display::new");
+
+        let terminal = Term::new(&config, display.size().to_owned());
+        let terminal = Arc::new(FairMutex::new(terminal));
+        let child = Child::spawn(id, shared, &config, &terminal);
+
+        State {
+            config, config_generation, display, terminal, child,
+            parser: ansi::Processor::new(),
+            mouse: MouseState::default(),
+            event_queue: Vec::new()
+        }
+    }
+
+    fn park(self) -> Parked {
+        let State { config, config_generation, terminal, child, event_queue, .. } = self;
+        Parked { config, config_generation, terminal, child, event_queue }
+    }
+
+    /// Replaces the whole configuration, reloading the fonts
+    fn set_config(&mut self, config: Config, scale: f32) {
+        let fontdiff = (config.font().size.as_f32_pts() - self.config.font().size.as_f32_pts()) as i8;
+        self.config = config;
+        self.event_queue.push(Event::ChangeFontSize(fontdiff));
+        // force reload the glyph cache if the size didn't change
+        self.event_queue.push(Event::HiDPIFactorChanged(scale));
+        self.event_queue.push(Event::ConfigUpdated);
+    }
+
+    /// Returns the currently selected text, if there's a non-empty selection
+    pub fn selection_to_string(&self) -> Option<String> {
+        self.terminal.lock().selection_to_string().filter(|s| !s.is_empty())
+    }
+
+    /// The kitty keyboard protocol flags the application has pushed, 0 for legacy input
+    pub fn keyboard_flags(&self) -> u8 {
+        self.child.input_modes.lock().expect("input modes").keyboard_flags()
+    }
+
+    /// Whether the application has enabled bracketed paste mode
+    pub fn bracketed_paste(&self) -> bool {
+        self.terminal.lock().mode().contains(TermMode::BRACKETED_PASTE)
+    }
+
+    /// Returns the text on the screen, without trailing whitespace on each line
+    pub fn text(&self) -> String {
+        let terminal = self.terminal.lock();
+        let grid = terminal.grid();
+        let mut text = String::new();
+        for line in 0..grid.num_lines().0 {
+            let row = &grid[Line(line)];
+            let line_text = (0..grid.num_cols().0)
+                .map(|col| &row[Column(col)])
+                .filter(|cell| !cell.flags.contains(CellFlags::WIDE_CHAR_SPACER))
+                .map(|cell| cell.c)
+                .collect::<String>();
+            text.push_str(line_text.trim_end());
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Remembers the pointer position, converting from widget coordinates to device pixels
+    fn set_mouse_position(&mut self, (x, y): (f64, f64), scale: i32) {
+        self.mouse.x = (x.max(0.0) * scale as f64) as usize;
+        self.mouse.y = (y.max(0.0) * scale as f64) as usize;
+    }
+
+    /// Which half of the cell under the pointer is hovered, like in Alacritty's input handling
+    fn mouse_side(&self) -> Side {
+        let size = self.display.size();
+        let x = self.mouse.x;
+        let cell_x = x.saturating_sub(size.padding_x as usize) % size.cell_width as usize;
+        let half_cell_width = (size.cell_width / 2.0) as usize;
+        let additional_padding = (size.width - size.padding_x * 2.) % size.cell_width;
+        let end_of_grid = size.width - size.padding_x - additional_padding;
+        if cell_x > half_cell_width || x as f32 >= end_of_grid {
+            Side::Right
+        } else {
+            Side::Left
+        }
+    }
+
+    /// Whether pointer events should go to the application instead of selecting text
+    ///
+    /// Holding Shift always selects locally, like in xterm.
+    fn reports_mouse(&self, mods: Mod) -> bool {
+        !mods.contains(Mod::SHIFT_MASK) && mouse::Mode::from_term(self.terminal.lock().mode()).is_active()
+    }
+
+    fn report_mouse(&mut self, button: mouse::Button, action: mouse::Action, mods: Mod) {
+        let report = {
+            let terminal = self.terminal.lock();
+            let point = match terminal.pixels_to_coords(self.mouse.x, self.mouse.y) {
+                Some(point) => point,
+                None => return,
+            };
+            let cell = (point.col.0, point.line.0);
+            if action == mouse::Action::Motion && self.mouse.last_report_cell == Some(cell) {
+                return;
+            }
+            self.mouse.last_report_cell = Some(cell);
+            mouse::report(mouse::Mode::from_term(terminal.mode()), button, action, mods, cell.0, cell.1)
+        };
+        if let Some(bytes) = report {
+            self.event_queue.push(Event::BytesInput(bytes));
+        }
+    }
+
+    /// Handles vertical scrolling by `delta` wheel notches, negative is up
+    fn scroll(&mut self, delta: f64, mods: Mod) {
+        self.mouse.scroll_delta += delta;
+        let notches = self.mouse.scroll_delta.trunc();
+        self.mouse.scroll_delta -= notches;
+        let notches = notches as isize;
+        if notches == 0 {
+            return;
+        }
+        if self.reports_mouse(mods) {
+            let button = if notches < 0 { mouse::Button::WheelUp } else { mouse::Button::WheelDown };
+            for _ in 0..notches.abs() {
+                self.report_mouse(button, mouse::Action::Press, mods);
+            }
+            return;
+        }
+        let mode = *self.terminal.lock().mode();
+        let scrolling = self.config.scrolling();
+        if mode.contains(TermMode::ALT_SCREEN | TermMode::ALTERNATE_SCROLL) && !mods.contains(Mod::SHIFT_MASK) {
+            // there's no scrollback on the alternate screen, let the application scroll instead
+            let key = match (notches < 0, mode.contains(TermMode::APP_CURSOR)) {
+                (true, true) => "\x1bOA",
+                (true, false) => "\x1b[A",
+                (false, true) => "\x1bOB",
+                (false, false) => "\x1b[B",
+            };
+            for _ in 0..notches.abs() as usize * scrolling.faux_multiplier as usize {
+                self.event_queue.push(Event::StrInput(key));
+            }
+        } else {
+            self.event_queue.push(Event::Scroll(Scroll::Lines(-notches * scrolling.multiplier as isize)));
+        }
+    }
+
+    fn start_selection(&mut self, click: gdk::EventType, mods: Mod) {
+        let side = self.mouse_side();
+        let mut terminal = self.terminal.lock();
+        let point = match terminal.pixels_to_coords(self.mouse.x, self.mouse.y) {
+            Some(point) => point,
+            None => return,
+        };
+        self.mouse.press_point = None;
+        *terminal.selection_mut() = match click {
+            gdk::EventType::DoubleButtonPress => Some(Selection::semantic(terminal.visible_to_buffer(point))),
+            gdk::EventType::TripleButtonPress => Some(Selection::lines(terminal.visible_to_buffer(point))),
+            _ => {
+                // Simple and block selections only start once the pointer is dragged,
+                // so that a plain click just clears the selection
+                self.mouse.block_selection = mods.contains(Mod::CONTROL_MASK);
+                self.mouse.press_point = Some((point, side));
+                None
+            },
+        };
+        terminal.dirty = true;
+    }
+
+    fn update_selection(&mut self) {
+        let side = self.mouse_side();
+        let mut terminal = self.terminal.lock();
+        let point = match terminal.pixels_to_coords(self.mouse.x, self.mouse.y) {
+            Some(point) => terminal.visible_to_buffer(point),
+            None => return,
+        };
+        if let Some((start, start_side)) = self.mouse.press_point.take() {
+            let start = terminal.visible_to_buffer(start);
+            *terminal.selection_mut() = Some(if self.mouse.block_selection {
+                Selection::block(start, start_side)
+            } else {
+                Selection::simple(start, start_side)
+            });
+        }
+        if let Some(ref mut selection) = *terminal.selection_mut() {
+            selection.update(point, side);
+        }
+        terminal.dirty = true;
+    }
+}
+
+/// Pastes text into the terminal, asking first if it looks risky, like the Paste action
 ///
-/// Eventually should be a GObject subclass, usable outside of Rust.
-pub fn alacritty_widget(window: gtk::ApplicationWindow, header_bar: gtk::HeaderBar) -> (gtk::GLArea, Rc<RefCell<Option<State>>>) {
+/// NOTE: the dialog runs the main loop, the state must not be borrowed here
+fn paste_checked(glarea: &gtk::GLArea, state: &RefCell<Option<State>>, text: String) {
+    let bracketed = state.borrow().as_ref().map(|state| state.bracketed_paste()).unwrap_or(false);
+    let window = glarea.get_toplevel().and_then(|w| w.downcast::<gtk::Window>().ok());
+    if let Some(text) = paste::check(window.as_ref(), text, bracketed) {
+        if let Some(ref mut state) = *state.borrow_mut() {
+            state.event_queue.push(Event::Paste(text));
+        }
+        glarea.queue_draw();
+    }
+}
+
+/// Creates a GLArea that runs an Alacritty terminal emulator,
+/// inside of an Overlay that also contains a scrollbar for the scrollback.
+fn alacritty_widget(id: usize, shared: Rc<Shared>, state: Rc<RefCell<Option<State>>>,
+                    parked: Rc<RefCell<Option<Parked>>>) -> (gtk::Overlay, gtk::GLArea) {
     let glarea = gtk::GLArea::new();
 
+    let adjustment = gtk::Adjustment::new(0.0, 0.0, 0.0, 1.0, 0.0, 0.0);
+    let scrollbar = gtk::Scrollbar::new(gtk::Orientation::Vertical, Some(&adjustment));
+    scrollbar.set_halign(gtk::Align::End);
+    if let Some(ctx) = scrollbar.get_style_context() {
+        ctx.add_class("overlay-indicator");
+    }
+
+    let overlay = gtk::Overlay::new();
+    overlay.add(&glarea);
+    overlay.add_overlay(&scrollbar);
+
+    // set while the adjustment is being synced with the terminal, to not scroll in response to that
+    let syncing_adjustment = Rc::new(Cell::new(false));
+
     let im = gtk::IMMulticontext::new();
     im.set_use_preedit(false);
 
-    let state: Rc<RefCell<Option<State>>> = Rc::new(RefCell::new(None));
+    // the off phase of cursor blinking
+    let cursor_hidden = Rc::new(Cell::new(false));
 
-    glarea.connect_realize(clone!(state, im => move |glarea| {
+    // hardware keycodes of the keys that are down, to tell repeats from presses
+    let held_keys: Rc<RefCell<HashSet<u16>>> = Rc::new(RefCell::new(HashSet::new()));
+
+    glarea.connect_realize(clone!(state, parked, im, shared => move |glarea| {
         let mut state = state.borrow_mut();
         im.set_client_window(glarea.get_window().as_ref());
         glarea.make_current();
@@ -92,77 +488,98 @@ pub fn alacritty_widget(window: gtk::ApplicationWindow, header_bar: gtk::HeaderB
         });
         gl::load_with(epoxy::get_proc_addr);
 
-        let config = Config::default();
-        let mut options = cli::Options::default();
-        options.print_events = true;
-
-        let display = Display::new(
-            &config,
-            InitialSize::Cells(config.dimensions()),
-            glarea.get_scale_factor() as f32
-        ).expect("Display::new");
-
-        let terminal = Term::new(&config, display.size().to_owned());
-        let terminal = Arc::new(FairMutex::new(terminal));
-
-        let pty = tty::new(&config, &options, &display.size(), None);
-        let pty_fd = pty.fd.as_raw_fd();
-
-        let event_loop = EventLoop::new(
-            Arc::clone(&terminal),
-            Box::new(Notifier),
-            pty,
-            options.ref_test,
-        );
+        if let Some(parked) = parked.borrow_mut().take() {
+            let display = Display::new(
+                &parked.config,
+                InitialSize::Cells(parked.config.dimensions()),
+                glarea.get_scale_factor() as f32
+            ).expect("Display::new");
+            parked.terminal.lock().dirty = true;
+            let Parked { config, config_generation, terminal, child, event_queue } = parked;
+            *state = Some(State {
+                config, config_generation, display, terminal, child,
+                parser: ansi::Processor::new(),
+                mouse: MouseState::default(),
+                event_queue
+            });
+            return;
+        }
 
-        let loop_notifier = event_loop::Notifier(event_loop.channel());
-        let io_thread = event_loop.spawn(None);
+        *state = Some(State::start(id, &shared, glarea));
+    }));
 
-        *state = Some(State {
-            config, display, terminal, pty_fd,
-            loop_notifier, io_thread,
-            event_queue: Vec::new()
-        });
+    glarea.connect_unrealize(clone!(state, parked => move |glarea| {
+        let mut state = state.borrow_mut();
+        if let Some(state) = state.take() {
+            // the display's GL resources have to be freed in the right context
+            glarea.make_current();
+            *parked.borrow_mut() = Some(state.park());
+        }
     }));
 
-    glarea.connect_unrealize(clone!(state => move |_widget| {
+    adjustment.connect_value_changed(clone!(state, glarea, syncing_adjustment => move |adjustment| {
+        if syncing_adjustment.get() {
+            return;
+        }
         let mut state = state.borrow_mut();
-        *state = None;
+        if let Some(ref mut state) = *state {
+            let delta = {
+                let terminal = state.terminal.lock();
+                let grid = terminal.grid();
+                let target = grid.history_size() as isize - adjustment.get_value().round() as isize;
+                target - grid.display_offset() as isize
+            };
+            if delta != 0 {
+                state.event_queue.push(Event::Scroll(Scroll::Lines(delta)));
+            }
+        }
+        glarea.queue_draw();
     }));
 
-    glarea.connect_render(clone!(state, im => move |_glarea, _glctx| {
+    glarea.connect_render(clone!(state, im, adjustment, syncing_adjustment, shared, cursor_hidden => move |glarea, _glctx| {
         let state_cell = &state;
         let mut state = state_cell.borrow_mut();
         let mut quit = false;
+        // signals are emitted after the state is unborrowed, handlers might want to use it
+        let (mut title_changed, mut bell, mut contents_changed) = (false, false, false);
         if let Some(ref mut state) = *state {
+            if state.config_generation != config::generation() {
+                state.config_generation = config::generation();
+                let config = config::for_profile(shared.profile.borrow().as_ref().map(|p| &**p));
+                state.set_config(config, glarea.get_scale_factor() as f32);
+            }
             let mut terminal = state.terminal.lock();
+            let exited = shared.exited.get();
             for event in state.event_queue.drain(..) {
                 match event {
                     Event::Blank => (),
-                    Event::CharInput(c, IsControlHeld(is_ctrl)) => {
-                        let len = c.len_utf8();
-                        let mut bytes = Vec::with_capacity(len);
-                        unsafe {
-                            bytes.set_len(len);
-                            c.encode_utf8(&mut bytes[..]);
-                        }
-                        if is_ctrl {
-                            for ch in bytes.iter_mut() {
-                                if *ch >= 0x40 && *ch < 0x80 {
-                                    *ch = *ch & !0x60;
-                                }
-                            }
-                        }
-                        use alacritty::event::Notify;
-                        state.loop_notifier.notify(bytes);
-                    },
+                    // nobody is listening on the other side of the pty anymore
+                    Event::StrInput(_) | Event::StringInput(_) |
+                        Event::KeyInput(_) | Event::BytesInput(_) | Event::Paste(_) if exited => (),
                     Event::StrInput(s) => {
+                        terminal.scroll_display(Scroll::Bottom);
                         use alacritty::event::Notify;
-                        state.loop_notifier.notify(s.as_bytes().to_vec());
+                        state.child.loop_notifier.notify(s.as_bytes().to_vec());
                     },
                     Event::StringInput(s) => {
+                        terminal.scroll_display(Scroll::Bottom);
+                        use alacritty::event::Notify;
+                        state.child.loop_notifier.notify(s.as_bytes().to_vec());
+                    },
+                    Event::KeyInput(b) => {
+                        terminal.scroll_display(Scroll::Bottom);
+                        use alacritty::event::Notify;
+                        state.child.loop_notifier.notify(b);
+                    },
+                    Event::BytesInput(b) => {
+                        use alacritty::event::Notify;
+                        state.child.loop_notifier.notify(b);
+                    },
+                    Event::Paste(s) => {
+                        terminal.scroll_display(Scroll::Bottom);
+                        let bracketed = terminal.mode().contains(TermMode::BRACKETED_PASTE);
                         use alacritty::event::Notify;
-                        state.loop_notifier.notify(s.as_bytes().to_vec());
+                        state.child.loop_notifier.notify(paste::to_bytes(&s, bracketed));
                     },
                     Event::WindowResized(w, h) => {
                         state.display.command_channel().send(DisplayCommand::NewSize(w, h)).expect("send new size");
@@ -179,31 +596,75 @@ pub fn alacritty_widget(window: gtk::ApplicationWindow, header_bar: gtk::HeaderB
                     },
                     Event::ResetFontSize => {
                         terminal.reset_font_size();
-                    }
+                    },
+                    Event::Scroll(scroll) => {
+                        terminal.scroll_display(scroll);
+                    },
+                    Event::Feed(data) => {
+                        for byte in data {
+                            state.parser.advance(&mut *terminal, byte, &mut io::sink());
+                        }
+                    },
+                    Event::ConfigUpdated => {
+                        state.display.update_config(&state.config);
+                        terminal.update_config(&state.config);
+                        terminal.dirty = true;
+                    },
+                }
+            }
+            {
+                let grid = terminal.grid();
+                let lines = grid.num_lines().0 as f64;
+                let history = grid.history_size() as f64;
+                let value = history - grid.display_offset() as f64;
+                if adjustment.get_upper() != history + lines || adjustment.get_value() != value || adjustment.get_page_size() != lines {
+                    syncing_adjustment.set(true);
+                    adjustment.configure(value, 0.0, history + lines, 1.0, lines, lines);
+                    syncing_adjustment.set(false);
                 }
             }
             if let Some(title) = terminal.get_next_title() {
-                header_bar.set_title(&*title);
+                *shared.title.borrow_mut() = Some(title);
+                title_changed = true;
+            }
+            if let Some(true) = terminal.next_is_urgent.take() {
+                bell = true;
             }
             if terminal.needs_draw() {
+                contents_changed = true;
                 let (x, y) = state.display.current_xim_spot(&terminal);
                 let &SizeInfo { cell_width, cell_height, .. } = state.display.size();
                 im.set_cursor_location(&gtk::Rectangle {
                     x: x.into(), y: y.into(), width: cell_width as i32, height: cell_height as i32
                 });
-                state.display.handle_resize(&mut terminal, &state.config, &mut [&mut state.pty_fd]);
+                let mut pty_fd = state.child.master.fd();
+                state.display.handle_resize(&mut terminal, &state.config, &mut [&mut pty_fd]);
                 drop(terminal); // mutex unlock
-                state.display.draw(&state.terminal, &state.config, true);
+                // the renderer can't leave the cursor out, the off phase of a blink is the
+                // hollow one it draws for unfocused windows
+                state.display.draw(&state.terminal, &state.config, !cursor_hidden.get());
             }
-            if process_should_exit() {
+            if !exited && state.child.master.hung_up() {
                 quit = true;
             }
         }
         drop(state);
+        if title_changed {
+            emit(&shared.window_title_changed);
+        }
+        if bell {
+            emit(&shared.bell);
+        }
+        if contents_changed {
+            emit(&shared.contents_changed);
+        }
         if quit {
-            let state = state_cell.replace(None).expect("state");
-            let _ = state.io_thread.join();
-            window.destroy();
+            shared.exited.set(true);
+            if !shared.hold.get() {
+                let state = state_cell.replace(None).expect("state");
+                state.child.shutdown();
+            }
+            emit(&shared.child_exited);
         }
         Inhibit(false)
     }));
@@ -216,118 +677,178 @@ pub fn alacritty_widget(window: gtk::ApplicationWindow, header_bar: gtk::HeaderB
         glarea.queue_draw();
     }));
 
-    glarea.add_events(gdk::EventMask::KEY_PRESS_MASK.bits() as i32);
+    glarea.add_events((gdk::EventMask::KEY_PRESS_MASK |
+                       gdk::EventMask::BUTTON_PRESS_MASK |
+                       gdk::EventMask::BUTTON_RELEASE_MASK |
+                       gdk::EventMask::POINTER_MOTION_MASK |
+                       gdk::EventMask::SCROLL_MASK |
+                       gdk::EventMask::SMOOTH_SCROLL_MASK).bits() as i32);
+
+    let primary = gtk::Clipboard::get(&gdk::Atom::intern("PRIMARY"));
+
+    glarea.connect_button_press_event(clone!(state, primary => move |glarea, event| {
+        glarea.grab_focus();
+        if event.get_button() == 2 && event.get_event_type() == gdk::EventType::ButtonPress {
+            let reports = match *state.borrow() {
+                Some(ref state) => state.reports_mouse(event.get_state()),
+                None => true,
+            };
+            if !reports {
+                // NOTE: wait_for_text runs the main loop, the state must not be borrowed here
+                if let Some(text) = primary.wait_for_text() {
+                    paste_checked(glarea, &state, text);
+                }
+                return Inhibit(true);
+            }
+        }
+        let mut state = state.borrow_mut();
+        if let Some(ref mut state) = *state {
+            state.set_mouse_position(event.get_position(), glarea.get_scale_factor());
+            let mods = event.get_state();
+            if state.reports_mouse(mods) {
+                // applications count multiple clicks themselves
+                if event.get_event_type() == gdk::EventType::ButtonPress {
+                    if let Some(button) = mouse::Button::from_gdk(event.get_button()) {
+                        state.mouse.reported_buttons.insert(event.get_button());
+                        state.report_mouse(button, mouse::Action::Press, mods);
+                    }
+                }
+            } else if event.get_button() == 1 {
+                state.mouse.left_pressed = true;
+                state.start_selection(event.get_event_type(), mods);
+            }
+        }
+        glarea.queue_draw();
+        Inhibit(true)
+    }));
+
+    glarea.connect_motion_notify_event(clone!(state => move |glarea, event| {
+        let mut state = state.borrow_mut();
+        if let Some(ref mut state) = *state {
+            state.set_mouse_position(event.get_position(), glarea.get_scale_factor());
+            let mods = event.get_state();
+            if state.mouse.left_pressed {
+                state.update_selection();
+                glarea.queue_draw();
+            } else if state.reports_mouse(mods) {
+                state.report_mouse(mouse::Button::from_state(mods), mouse::Action::Motion, mods);
+                glarea.queue_draw();
+            }
+        }
+        Inhibit(true)
+    }));
+
+    glarea.connect_button_release_event(clone!(state => move |glarea, event| {
+        let mut state = state.borrow_mut();
+        if let Some(ref mut state) = *state {
+            state.set_mouse_position(event.get_position(), glarea.get_scale_factor());
+            let mods = event.get_state();
+            // whether the release is reported depends on the press, not on what's held now
+            if state.mouse.reported_buttons.remove(&event.get_button()) {
+                if let Some(button) = mouse::Button::from_gdk(event.get_button()) {
+                    state.report_mouse(button, mouse::Action::Release, mods);
+                }
+            } else if event.get_button() == 1 {
+                state.mouse.left_pressed = false;
+                state.mouse.press_point = None;
+                if let Some(text) = state.selection_to_string() {
+                    primary.set_text(&text);
+                }
+            }
+        }
+        glarea.queue_draw();
+        Inhibit(true)
+    }));
+
+    glarea.connect_scroll_event(clone!(state => move |glarea, event| {
+        let mut state = state.borrow_mut();
+        if let Some(ref mut state) = *state {
+            state.set_mouse_position(event.get_position(), glarea.get_scale_factor());
+            let mods = event.get_state();
+            match event.get_direction() {
+                gdk::ScrollDirection::Up => state.scroll(-1.0, mods),
+                gdk::ScrollDirection::Down => state.scroll(1.0, mods),
+                gdk::ScrollDirection::Smooth => {
+                    let (_dx, dy) = event.get_delta();
+                    state.scroll(dy, mods);
+                },
+                gdk::ScrollDirection::Left if state.reports_mouse(mods) => {
+                    state.report_mouse(mouse::Button::WheelLeft, mouse::Action::Press, mods);
+                },
+                gdk::ScrollDirection::Right if state.reports_mouse(mods) => {
+                    state.report_mouse(mouse::Button::WheelRight, mouse::Action::Press, mods);
+                },
+                _ => (),
+            }
+        }
+        glarea.queue_draw();
+        Inhibit(true)
+    }));
 
-    glarea.connect_key_press_event(clone!(state, im => move |glarea, event| {
-        if im.filter_keypress(event) {
+    glarea.connect_key_press_event(clone!(state, im, cursor_hidden, held_keys => move |glarea, event| {
+        cursor_hidden.set(false);
+        let repeat = !held_keys.borrow_mut().insert(event.get_hardware_keycode());
+        let flags = state.borrow().as_ref().map(|s| s.keyboard_flags()).unwrap_or(0);
+        // applications that want every key as an escape code get them before the input method
+        if flags & kitty::ALL_KEYS == 0 && im.filter_keypress(event) {
             return Inhibit(true);
         }
-        let kv = event.get_keyval();
+        let mods = event.get_state();
+        let kv = keys::latin_keyval(event.get_keyval(), event.get_hardware_keycode(), mods);
         trace!("non-IM input: keyval {:?} unicode {:?}", kv, gdk::keyval_to_unicode(kv));
         let mut state = state.borrow_mut();
         if let Some(ref mut state) = *state {
             use gdk::enums::key::*;
-            let mods = event.get_state();
-            // TODO: make this dynamically configurable
+            let modify_other_keys = state.child.input_modes.lock().expect("input modes").modify_other_keys();
+            let mode = keys::Mode::from_term(state.terminal.lock().mode(), modify_other_keys);
+            let options = prefs::get().keys;
+            let kitty_key = if flags != 0 {
+                let action = if repeat { kitty::Action::Repeat } else { kitty::Action::Press };
+                kitty::encode(&kitty::Key::from_event(event, action), flags)
+            } else {
+                None
+            };
             #[allow(non_upper_case_globals)] // they're not mine, why complain here?!
             state.event_queue.push(match kv {
-                Page_Up | KP_Page_Up if mods.contains(Mod::SHIFT_MASK) => Event::StrInput("\x1b[5;2~"),
-                Page_Up | KP_Page_Up if mods.contains(Mod::CONTROL_MASK) => Event::StrInput("\x1b[5;5~"),
-                Page_Up | KP_Page_Up => Event::StrInput("\x1b[5~"),
-                Page_Down | KP_Page_Down if mods.contains(Mod::SHIFT_MASK) => Event::StrInput("\x1b[6;2~"),
-                Page_Down | KP_Page_Down if mods.contains(Mod::CONTROL_MASK) => Event::StrInput("\x1b[6;5~"),
-                Page_Down | KP_Page_Down => Event::StrInput("\x1b[6~"),
-                Tab if mods.contains(Mod::SHIFT_MASK) => Event::StrInput("\x1b[Z"),
-                Insert => Event::StrInput("\x1b[2~"),
-                Delete | KP_Delete => Event::StrInput("\x1b[3~"),
-                Left | KP_Left if mods.contains(Mod::SHIFT_MASK) => Event::StrInput("\x1b[1;2D"),
-                Left | KP_Left if mods.contains(Mod::META_MASK) => Event::StrInput("\x1b[1;3D"),
-                Left | KP_Left if mods.contains(Mod::CONTROL_MASK) => Event::StrInput("\x1b[1;5D"),
-                Left | KP_Left => Event::StrInput("\x1b[D"),
-                Right | KP_Right if mods.contains(Mod::SHIFT_MASK) => Event::StrInput("\x1b[1;2C"),
-                Right | KP_Right if mods.contains(Mod::META_MASK) => Event::StrInput("\x1b[1;3C"),
-                Right | KP_Right if mods.contains(Mod::CONTROL_MASK) => Event::StrInput("\x1b[1;5C"),
-                Right | KP_Right => Event::StrInput("\x1b[C"),
-                Up | KP_Up if mods.contains(Mod::SHIFT_MASK) => Event::StrInput("\x1b[1;2A"),
-                Up | KP_Up if mods.contains(Mod::META_MASK) => Event::StrInput("\x1b[1;3A"),
-                Up | KP_Up if mods.contains(Mod::CONTROL_MASK) => Event::StrInput("\x1b[1;5A"),
-                Up | KP_Up => Event::StrInput("\x1b[A"),
-                Down | KP_Down if mods.contains(Mod::SHIFT_MASK) => Event::StrInput("\x1b[1;2B"),
-                Down | KP_Down if mods.contains(Mod::META_MASK) => Event::StrInput("\x1b[1;3B"),
-                Down | KP_Down if mods.contains(Mod::CONTROL_MASK) => Event::StrInput("\x1b[1;5B"),
-                Down | KP_Down => Event::StrInput("\x1b[B"),
-                F1 if mods.contains(Mod::SHIFT_MASK) => Event::StrInput("\x1b[1;2P"),
-                F1 if mods.contains(Mod::SUPER_MASK) => Event::StrInput("\x1b[1;3P"),
-                F1 if mods.contains(Mod::CONTROL_MASK) => Event::StrInput("\x1b[1;5P"),
-                F1 if mods.contains(Mod::META_MASK) => Event::StrInput("\x1b[1;6P"),
-                F1 => Event::StrInput("\x1bOP"),
-                F2 if mods.contains(Mod::SHIFT_MASK) => Event::StrInput("\x1b[1;2Q"),
-                F2 if mods.contains(Mod::SUPER_MASK) => Event::StrInput("\x1b[1;3Q"),
-                F2 if mods.contains(Mod::CONTROL_MASK) => Event::StrInput("\x1b[1;5Q"),
-                F2 if mods.contains(Mod::META_MASK) => Event::StrInput("\x1b[1;6Q"),
-                F2 => Event::StrInput("\x1bOQ"),
-                F3 if mods.contains(Mod::SHIFT_MASK) => Event::StrInput("\x1b[1;2R"),
-                F3 if mods.contains(Mod::SUPER_MASK) => Event::StrInput("\x1b[1;3R"),
-                F3 if mods.contains(Mod::CONTROL_MASK) => Event::StrInput("\x1b[1;5R"),
-                F3 if mods.contains(Mod::META_MASK) => Event::StrInput("\x1b[1;6R"),
-                F3 => Event::StrInput("\x1bOR"),
-                F4 if mods.contains(Mod::SHIFT_MASK) => Event::StrInput("\x1b[1;2S"),
-                F4 if mods.contains(Mod::SUPER_MASK) => Event::StrInput("\x1b[1;3S"),
-                F4 if mods.contains(Mod::CONTROL_MASK) => Event::StrInput("\x1b[1;5S"),
-                F4 if mods.contains(Mod::META_MASK) => Event::StrInput("\x1b[1;6S"),
-                F4 => Event::StrInput("\x1bOS"),
-                F5 if mods.contains(Mod::SHIFT_MASK) => Event::StrInput("\x1b[15;2~"),
-                F5 if mods.contains(Mod::SUPER_MASK) => Event::StrInput("\x1b[15;3~"),
-                F5 if mods.contains(Mod::CONTROL_MASK) => Event::StrInput("\x1b[15;5~"),
-                F5 if mods.contains(Mod::META_MASK) => Event::StrInput("\x1b[15;6~"),
-                F5 => Event::StrInput("\x1b[15~"),
-                F6 if mods.contains(Mod::SHIFT_MASK) => Event::StrInput("\x1b[17;2~"),
-                F6 if mods.contains(Mod::SUPER_MASK) => Event::StrInput("\x1b[17;3~"),
-                F6 if mods.contains(Mod::CONTROL_MASK) => Event::StrInput("\x1b[17;5~"),
-                F6 if mods.contains(Mod::META_MASK) => Event::StrInput("\x1b[17;6~"),
-                F6 => Event::StrInput("\x1b[17~"),
-                F7 if mods.contains(Mod::SHIFT_MASK) => Event::StrInput("\x1b[18;2~"),
-                F7 if mods.contains(Mod::SUPER_MASK) => Event::StrInput("\x1b[18;3~"),
-                F7 if mods.contains(Mod::CONTROL_MASK) => Event::StrInput("\x1b[18;5~"),
-                F7 if mods.contains(Mod::META_MASK) => Event::StrInput("\x1b[18;6~"),
-                F7 => Event::StrInput("\x1b[18~"),
-                F8 if mods.contains(Mod::SHIFT_MASK) => Event::StrInput("\x1b[19;2~"),
-                F8 if mods.contains(Mod::SUPER_MASK) => Event::StrInput("\x1b[19;3~"),
-                F8 if mods.contains(Mod::CONTROL_MASK) => Event::StrInput("\x1b[19;5~"),
-                F8 if mods.contains(Mod::META_MASK) => Event::StrInput("\x1b[19;6~"),
-                F8 => Event::StrInput("\x1b[19~"),
-                F9 if mods.contains(Mod::SHIFT_MASK) => Event::StrInput("\x1b[20;2~"),
-                F9 if mods.contains(Mod::SUPER_MASK) => Event::StrInput("\x1b[20;3~"),
-                F9 if mods.contains(Mod::CONTROL_MASK) => Event::StrInput("\x1b[20;5~"),
-                F9 if mods.contains(Mod::META_MASK) => Event::StrInput("\x1b[20;6~"),
-                F9 => Event::StrInput("\x1b[20~"),
-                F10 if mods.contains(Mod::SHIFT_MASK) => Event::StrInput("\x1b[21;2~"),
-                F10 if mods.contains(Mod::SUPER_MASK) => Event::StrInput("\x1b[21;3~"),
-                F10 if mods.contains(Mod::CONTROL_MASK) => Event::StrInput("\x1b[21;5~"),
-                F10 if mods.contains(Mod::META_MASK) => Event::StrInput("\x1b[21;6~"),
-                F10 => Event::StrInput("\x1b[21~"),
-                F11 if mods.contains(Mod::SHIFT_MASK) => Event::StrInput("\x1b[23;2~"),
-                F11 if mods.contains(Mod::SUPER_MASK) => Event::StrInput("\x1b[23;3~"),
-                F11 if mods.contains(Mod::CONTROL_MASK) => Event::StrInput("\x1b[23;5~"),
-                F11 if mods.contains(Mod::META_MASK) => Event::StrInput("\x1b[23;6~"),
-                F11 => Event::StrInput("\x1b[23~"),
-                F12 if mods.contains(Mod::SHIFT_MASK) => Event::StrInput("\x1b[24;2~"),
-                F12 if mods.contains(Mod::SUPER_MASK) => Event::StrInput("\x1b[24;3~"),
-                F12 if mods.contains(Mod::CONTROL_MASK) => Event::StrInput("\x1b[24;5~"),
-                F12 if mods.contains(Mod::META_MASK) => Event::StrInput("\x1b[24;6~"),
-                F12 => Event::StrInput("\x1b[24~"),
-                Super_L | Super_R | Hyper_L | Hyper_R | Control_L | Control_R |
-                    Alt_L | Alt_R | Meta_L | Meta_R | Shift_L | Shift_R |
-                    Caps_Lock | Scroll_Lock | Shift_Lock | ModeLock => Event::Blank,
-                _ => Event::CharInput(gdk::keyval_to_unicode(kv).unwrap_or(kv as u8 as char), IsControlHeld(mods.contains(Mod::CONTROL_MASK))),
+                // scrollback keys that are handled here instead of being sent
+                Page_Up | KP_Page_Up if mods.contains(Mod::SHIFT_MASK) => Event::Scroll(Scroll::PageUp),
+                Page_Down | KP_Page_Down if mods.contains(Mod::SHIFT_MASK) => Event::Scroll(Scroll::PageDown),
+                Home | KP_Home if mods.contains(Mod::SHIFT_MASK) => Event::Scroll(Scroll::Top),
+                End | KP_End if mods.contains(Mod::SHIFT_MASK) => Event::Scroll(Scroll::Bottom),
+                _ => match kitty_key {
+                    Some(ref bytes) if bytes.is_empty() => Event::Blank,
+                    Some(bytes) => Event::KeyInput(bytes),
+                    None if keys::is_modifier(kv) => Event::Blank,
+                    None => match keys::encode(kv, mods, mode, &options) {
+                        Some(bytes) => Event::KeyInput(bytes),
+                        // keys that produce no character, like multimedia keys, send nothing
+                        None => match gdk::keyval_to_unicode(kv) {
+                            Some(c) => Event::KeyInput(keys::encode_char(c, mods, mode, &options)),
+                            None => Event::Blank,
+                        },
+                    },
+                },
             });
         }
         glarea.queue_draw();
-        Inhibit(kv == gdk::enums::key::Tab) // prevent tab from switching focus to the top bar
+        // prevent tab from switching focus to the top bar
+        Inhibit(kv == gdk::enums::key::Tab || kv == gdk::enums::key::ISO_Left_Tab)
     }));
 
-    glarea.connect_key_release_event(clone!(im => move |_glarea, event| {
+    glarea.connect_key_release_event(clone!(state, im, held_keys => move |glarea, event| {
+        held_keys.borrow_mut().remove(&event.get_hardware_keycode());
         let _ = im.filter_keypress(event);
+        let mut state = state.borrow_mut();
+        if let Some(ref mut state) = *state {
+            let flags = state.keyboard_flags();
+            if flags & kitty::EVENT_TYPES != 0 {
+                let key = kitty::Key::from_event(event, kitty::Action::Release);
+                if let Some(bytes) = kitty::encode(&key, flags).filter(|b| !b.is_empty()) {
+                    state.event_queue.push(Event::KeyInput(bytes));
+                    glarea.queue_draw();
+                }
+            }
+        }
         Inhibit(true)
     }));
 
@@ -344,16 +865,20 @@ pub fn alacritty_widget(window: gtk::ApplicationWindow, header_bar: gtk::HeaderB
     glarea.drag_dest_add_text_targets();
     glarea.drag_dest_add_uri_targets();
 
-    glarea.connect_drag_data_received(clone!(state => move |_glarea, _dctx, _x, _y, data, _info, _time| {
-        let mut state = state.borrow_mut();
-        if let Some(ref mut state) = *state {
-            let uris = data.get_uris();
-            if uris.len() > 0 {
-                state.event_queue.push(Event::StringInput(uris.iter().map(|u| u.trim().replace("file://", "")).collect::<Vec<_>>().join(" ")));
-            } else if let Some(text) = data.get_text() {
-                state.event_queue.push(Event::StringInput(text.replace("file://", "").trim().to_owned()));
-            }
-        }
+    glarea.connect_drag_data_received(clone!(state => move |glarea, _dctx, _x, _y, data, _info, _time| {
+        let uris = data.get_uris();
+        let text = if uris.len() > 0 {
+            uris.iter().map(|u| u.trim().replace("file://", "")).collect::<Vec<_>>().join(" ")
+        } else if let Some(text) = data.get_text() {
+            text.replace("file://", "").trim().to_owned()
+        } else {
+            return;
+        };
+        // the drag has to finish before a confirmation dialog can run
+        gtk::idle_add(clone!(glarea, state => move || {
+            paste_checked(&glarea, &state, text.clone());
+            glib::Continue(false)
+        }));
     }));
 
     glarea.connect_property_scale_factor_notify(clone!(state => move |glarea| {
@@ -369,18 +894,299 @@ pub fn alacritty_widget(window: gtk::ApplicationWindow, header_bar: gtk::HeaderB
         im.focus_in();
         Inhibit(false)
     }));
-    glarea.connect_focus_out_event(clone!(im => move |_glarea, _event| {
+    glarea.connect_focus_out_event(clone!(im, held_keys => move |_glarea, _event| {
         im.focus_out();
+        // the releases go to whatever has the focus now
+        held_keys.borrow_mut().clear();
         Inhibit(false)
     }));
     glarea.grab_focus();
 
-    GLOBAL.with(clone!(glarea => move |global| {
+    let blink_timer = gtk::timeout_add(600, clone!(state, glarea, cursor_hidden => move || {
+        let hide = prefs::get().cursor_blink && glarea.has_focus() && !cursor_hidden.get();
+        if hide != cursor_hidden.get() {
+            cursor_hidden.set(hide);
+            if let Some(ref state) = *state.borrow() {
+                state.terminal.lock().dirty = true;
+            }
+            glarea.queue_draw();
+        }
+        glib::Continue(true)
+    }));
+    let blink_timer = RefCell::new(Some(blink_timer));
+
+    REGISTRY.with(clone!(glarea => move |registry| {
         // NOTE: important to store glarea somewhere, adding to window doesn't prevent from
         // being dropped at the end of the scope https://github.com/gtk-rs/gtk/issues/637
         // (conveniently, we need to store it for the notifier here)
-        *global.borrow_mut() = Some(glarea);
+        registry.borrow_mut().insert(id, glarea);
+    }));
+
+    glarea.connect_destroy(clone!(state => move |_glarea| {
+        REGISTRY.with(|registry| registry.borrow_mut().remove(&id));
+        if let Some(timer) = blink_timer.borrow_mut().take() {
+            glib::source_remove(timer);
+        }
+        if let Some(state) = state.borrow_mut().take() {
+            state.child.shutdown();
+        }
+        if let Some(parked) = parked.borrow_mut().take() {
+            parked.child.shutdown();
+        }
+        // the handlers usually hold Terminal handles, which hold the shared state
+        shared.child_exited.borrow_mut().clear();
+        shared.window_title_changed.borrow_mut().clear();
+        shared.bell.borrow_mut().clear();
+        shared.contents_changed.borrow_mut().clear();
     }));
 
-    (glarea, state)
+    (overlay, glarea)
+}
+
+/// A terminal widget, with an API modeled after VteTerminal.
+///
+/// The widget is a GalacrittyTerminal, which has the signals and
+/// the settings as GObject signals and properties (see the object module).
+#[derive(Clone)]
+pub struct Terminal {
+    container: gtk::Bin,
+    glarea: gtk::GLArea,
+    id: usize,
+    state: Rc<RefCell<Option<State>>>,
+    parked: Rc<RefCell<Option<Parked>>>,
+    shared: Rc<Shared>,
+}
+
+impl PartialEq for Terminal {
+    fn eq(&self, other: &Terminal) -> bool {
+        self.glarea == other.glarea
+    }
+}
+
+impl Terminal {
+    pub fn new() -> Terminal {
+        object::new()
+    }
+
+    /// Fills a new GalacrittyTerminal, the object module calls this
+    pub fn create(container: gtk::Bin) -> Terminal {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let shared = Rc::new(Shared::default());
+        let state = Rc::new(RefCell::new(None));
+        let parked = Rc::new(RefCell::new(None));
+        let (overlay, glarea) = alacritty_widget(id, shared.clone(), state.clone(), parked.clone());
+        container.add(&overlay);
+        overlay.show_all();
+        Terminal { container, glarea, id, state, parked, shared }
+    }
+
+    /// The widget to put into a container, a GalacrittyTerminal
+    pub fn widget(&self) -> &gtk::Bin {
+        &self.container
+    }
+
+    /// The widget that should have the keyboard focus
+    pub fn glarea(&self) -> &gtk::GLArea {
+        &self.glarea
+    }
+
+    /// Runs a closure on the state, if the terminal is running
+    pub fn with_state<R, F: FnOnce(&mut State) -> R>(&self, f: F) -> Option<R> {
+        match *self.state.borrow_mut() {
+            Some(ref mut state) => Some(f(state)),
+            None => None,
+        }
+    }
+
+    fn push_event(&self, event: Event) {
+        self.with_state(|state| state.event_queue.push(event));
+        self.glarea.queue_draw();
+    }
+
+    /// Runs a command instead of the shell, in the given working directory and
+    /// with additional environment variables.
+    ///
+    /// Before the widget is realized, this sets what it starts with. After that,
+    /// the command replaces the running child, or starts after the last one exited.
+    pub fn spawn(&self, argv: &[&str], working_directory: Option<&Path>, envv: &[(&str, &str)]) -> bool {
+        {
+            let mut spawn = self.shared.spawn.borrow_mut();
+            spawn.argv = argv.iter().map(|a| (*a).to_owned()).collect();
+            spawn.working_dir = working_directory.map(|d| d.to_owned());
+            spawn.env = envv.iter().map(|&(k, v)| (k.to_owned(), v.to_owned())).collect();
+        }
+        let mut state = self.state.borrow_mut();
+        let mut parked = self.parked.borrow_mut();
+        if let Some(ref mut state) = *state {
+            let child = Child::spawn(self.id, &self.shared, &state.config, &state.terminal);
+            mem::replace(&mut state.child, child).shutdown();
+        } else if let Some(ref mut parked) = *parked {
+            let child = Child::spawn(self.id, &self.shared, &parked.config, &parked.terminal);
+            mem::replace(&mut parked.child, child).shutdown();
+        } else if self.glarea.get_realized() {
+            // the child exited without hold, and the display went with it
+            self.glarea.make_current();
+            *state = Some(State::start(self.id, &self.shared, &self.glarea));
+        }
+        drop(state);
+        self.glarea.queue_draw();
+        true
+    }
+
+    /// Sets the profile to take the configuration from, None for the default one
+    pub fn set_profile(&self, profile: Option<&str>) {
+        *self.shared.profile.borrow_mut() = profile.map(|p| p.to_owned());
+        let scale = self.glarea.get_scale_factor() as f32;
+        self.with_state(|state| state.set_config(config::for_profile(profile), scale));
+        self.glarea.queue_draw();
+        self.container.notify("profile");
+    }
+
+    pub fn get_profile(&self) -> Option<String> {
+        self.shared.profile.borrow().clone()
+    }
+
+    /// Sets whether the terminal should stay around after the child exits
+    pub fn set_hold(&self, hold: bool) {
+        self.shared.hold.set(hold);
+        self.container.notify("hold");
+    }
+
+    pub fn get_hold(&self) -> bool {
+        self.shared.hold.get()
+    }
+
+    /// Interprets data as if it came from the child
+    pub fn feed(&self, data: &[u8]) {
+        self.push_event(Event::Feed(data.to_vec()));
+    }
+
+    /// Sends data to the child as if it was typed
+    pub fn feed_child(&self, data: &[u8]) {
+        self.push_event(Event::BytesInput(data.to_vec()));
+    }
+
+    /// Pastes text into the child, bracketed if the application asked for that
+    pub fn paste_text(&self, text: &str) {
+        self.push_event(Event::Paste(text.to_owned()));
+    }
+
+    pub fn copy_clipboard(&self) {
+        self.copy_to(&gtk::Clipboard::get(&gdk::Atom::intern("CLIPBOARD")));
+    }
+
+    pub fn copy_primary(&self) {
+        self.copy_to(&gtk::Clipboard::get(&gdk::Atom::intern("PRIMARY")));
+    }
+
+    fn copy_to(&self, clipboard: &gtk::Clipboard) {
+        if let Some(Some(text)) = self.with_state(|state| state.selection_to_string()) {
+            clipboard.set_text(&text);
+        }
+    }
+
+    pub fn paste_clipboard(&self) {
+        self.paste_from(&gtk::Clipboard::get(&gdk::Atom::intern("CLIPBOARD")));
+    }
+
+    pub fn paste_primary(&self) {
+        self.paste_from(&gtk::Clipboard::get(&gdk::Atom::intern("PRIMARY")));
+    }
+
+    /// Pastes text, asking first if the preferences say so and it looks risky
+    pub fn paste_checked(&self, text: String) {
+        paste_checked(&self.glarea, &self.state, text);
+    }
+
+    fn paste_from(&self, clipboard: &gtk::Clipboard) {
+        // NOTE: wait_for_text runs the main loop, the state must not be borrowed here
+        if let Some(text) = clipboard.wait_for_text() {
+            self.paste_checked(text);
+        }
+    }
+
+    pub fn get_has_selection(&self) -> bool {
+        self.with_state(|state| state.selection_to_string().is_some()).unwrap_or(false)
+    }
+
+    pub fn is_bracketed_paste(&self) -> bool {
+        self.with_state(|state| state.bracketed_paste()).unwrap_or(false)
+    }
+
+    /// Returns the text on the screen
+    pub fn get_text(&self) -> Option<String> {
+        self.with_state(|state| state.text())
+    }
+
+    /// Returns the title last set by the application
+    pub fn get_window_title(&self) -> Option<String> {
+        self.shared.title.borrow().clone()
+    }
+
+    pub fn get_config(&self) -> Option<Config> {
+        self.with_state(|state| state.config.clone())
+    }
+
+    pub fn get_font(&self) -> Option<Font> {
+        self.with_state(|state| state.config.font().clone())
+    }
+
+    pub fn set_font(&self, font: Font) {
+        let scale = self.glarea.get_scale_factor() as f32;
+        self.with_state(|state| {
+            let mut config = state.config.clone();
+            config.set_font(font);
+            state.set_config(config, scale);
+        });
+        self.glarea.queue_draw();
+        self.container.notify("font");
+    }
+
+    pub fn change_font_size(&self, delta: i8) {
+        self.push_event(Event::ChangeFontSize(delta));
+    }
+
+    pub fn reset_font_size(&self) {
+        self.push_event(Event::ResetFontSize);
+    }
+
+    pub fn set_colors(&self, colors: Colors) {
+        self.with_state(|state| {
+            state.config.set_colors(colors);
+            state.event_queue.push(Event::ConfigUpdated);
+        });
+        self.glarea.queue_draw();
+    }
+
+    /// Wraps a signal handler, it gets the Terminal without keeping the widget alive
+    fn handler<F: Fn(&Terminal) + 'static>(&self, f: F) -> Rc<dyn Fn()> {
+        let container = self.container.downgrade();
+        let (glarea, id) = (self.glarea.clone(), self.id);
+        let (state, parked, shared) = (self.state.clone(), self.parked.clone(), self.shared.clone());
+        Rc::new(move || {
+            if let Some(container) = container.upgrade() {
+                f(&Terminal {
+                    container, glarea: glarea.clone(), id,
+                    state: state.clone(), parked: parked.clone(), shared: shared.clone()
+                });
+            }
+        })
+    }
+
+    /// Emitted when the child hangs up on the pty, the exit status isn't known
+    pub fn connect_child_exited<F: Fn(&Terminal) + 'static>(&self, f: F) {
+        self.shared.child_exited.borrow_mut().push(self.handler(f));
+    }
+
+    pub fn connect_window_title_changed<F: Fn(&Terminal) + 'static>(&self, f: F) {
+        self.shared.window_title_changed.borrow_mut().push(self.handler(f));
+    }
+
+    pub fn connect_bell<F: Fn(&Terminal) + 'static>(&self, f: F) {
+        self.shared.bell.borrow_mut().push(self.handler(f));
+    }
+
+    pub fn connect_contents_changed<F: Fn(&Terminal) + 'static>(&self, f: F) {
+        self.shared.contents_changed.borrow_mut().push(self.handler(f));
+    }
 }